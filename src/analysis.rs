@@ -0,0 +1,482 @@
+use std::collections::HashSet;
+
+pub use num_bigint::BigUint;
+
+use crate::errors::LSystemError;
+use crate::system::LSystem;
+use crate::token::TokenId;
+
+/// How the length of the generations of a system grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Growth {
+    /// Lengths stay below a constant.
+    Bounded,
+    /// Lengths grow like `n^degree`.
+    Polynomial(usize),
+    /// Lengths grow like `λ^n` for the dominant eigenvalue `λ > 1`.
+    Exponential,
+}
+
+/// Growth properties of a deterministic, context-free system.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrowthAnalysis {
+    /// `matrix[i][j]` is the number of times token `j` appears in what token
+    /// `i` becomes after one step, with tokens in arena order.
+    pub matrix: Vec<Vec<u64>>,
+    /// Coefficients `c` of the linear recurrence satisfied by generation
+    /// lengths, `len(n) = c[0] * len(n - 1) + c[1] * len(n - 2) + ...`, for
+    /// `n >= c.len()`, the number of tokens reachable from the axiom. Trailing
+    /// zeros are kept: they account for tokens whose contribution dies out, so
+    /// early lengths may not follow a shorter recurrence. `None` if the
+    /// coefficients don't fit in an `i128`.
+    pub recurrence: Option<Vec<i128>>,
+    /// The asymptotic growth rate of generation lengths.
+    pub dominant_eigenvalue: f64,
+    pub growth: Growth,
+    /// The share of each token, in arena order, in generations far enough in
+    /// the future. Periodic systems are averaged over their period.
+    pub frequencies: Vec<f64>,
+}
+
+const POWER_ITERATIONS: usize = 10_000;
+const FREQUENCY_ITERATIONS: usize = 2048;
+
+/// Builds the growth matrix of `system`, see [`GrowthAnalysis::matrix`].
+pub fn growth_matrix(system: &LSystem) -> Result<Vec<Vec<u64>>, LSystemError> {
+    let tokens = system.arena().enumerate().map(|(id, _)| id).collect::<Vec<_>>();
+    let mut matrix = vec![vec![0; tokens.len()]; tokens.len()];
+
+    for (row, id) in tokens.iter().enumerate() {
+        let successor = system.grammar().successor_of(*id).ok_or(LSystemError::NotDeterministic)?;
+        for next in successor {
+            matrix[row][next.value() as usize] += 1;
+        }
+    }
+
+    Ok(matrix)
+}
+
+/// Analyses how the generations of `system` grow, considering only the tokens
+/// reachable from its axiom.
+pub fn analyze(system: &LSystem) -> Result<GrowthAnalysis, LSystemError> {
+    let matrix = growth_matrix(system)?;
+    let reachable = reachable(&matrix, system.axiom());
+
+    let growth = classify(&matrix, &reachable);
+
+    let dominant_eigenvalue = match growth {
+        Growth::Exponential => dominant_eigenvalue(&matrix, &reachable),
+        Growth::Polynomial(_) => 1.0,
+        // Bounded systems either die out or keep cycling through the same lengths.
+        Growth::Bounded if components(&matrix, &reachable).iter().any(|c| is_cyclic(&matrix, c)) => 1.0,
+        Growth::Bounded => 0.0,
+    };
+
+    Ok(GrowthAnalysis {
+        recurrence: recurrence(&matrix, &reachable),
+        dominant_eigenvalue,
+        growth,
+        frequencies: frequencies(&matrix, system.axiom()),
+        matrix,
+    })
+}
+
+/// Counts how many times each token, in arena order, appears `n` steps after
+/// the axiom, without expanding any generation. The growth matrix is raised
+/// to the `n`th power by repeated squaring.
+pub fn token_counts(system: &LSystem, n: usize) -> Result<Vec<BigUint>, LSystemError> {
+    let matrix = growth_matrix(system)?
+        .into_iter()
+        .map(|row| row.into_iter().map(BigUint::from).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let mut counts = vec![BigUint::ZERO; matrix.len()];
+    for id in system.axiom() {
+        counts[id.value() as usize] += 1u32;
+    }
+
+    let mut power = matrix;
+    let mut remaining = n;
+
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            counts = multiply_vector(&counts, &power);
+        }
+        remaining >>= 1;
+        if remaining > 0 {
+            power = multiply_matrices(&power, &power);
+        }
+    }
+
+    Ok(counts)
+}
+
+fn multiply_vector(vector: &[BigUint], matrix: &[Vec<BigUint>]) -> Vec<BigUint> {
+    let mut result = vec![BigUint::ZERO; vector.len()];
+
+    for (i, v) in vector.iter().enumerate() {
+        if *v == BigUint::ZERO {
+            continue;
+        }
+        for (j, result) in result.iter_mut().enumerate() {
+            if matrix[i][j] != BigUint::ZERO {
+                *result += v * &matrix[i][j];
+            }
+        }
+    }
+
+    result
+}
+
+fn multiply_matrices(a: &[Vec<BigUint>], b: &[Vec<BigUint>]) -> Vec<Vec<BigUint>> {
+    a.iter().map(|row| multiply_vector(row, b)).collect()
+}
+
+/// Indices of the tokens that appear in some generation.
+fn reachable(matrix: &[Vec<u64>], axiom: &[TokenId]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    let mut pending = axiom.iter().map(|id| id.value() as usize).collect::<Vec<_>>();
+
+    while let Some(i) = pending.pop() {
+        if seen.insert(i) {
+            pending.extend((0..matrix.len()).filter(|&j| matrix[i][j] > 0));
+        }
+    }
+
+    let mut reachable = seen.into_iter().collect::<Vec<_>>();
+    reachable.sort_unstable();
+    reachable
+}
+
+/// Strongly connected components of the graph restricted to `nodes`, in
+/// reverse topological order.
+fn components(matrix: &[Vec<u64>], nodes: &[usize]) -> Vec<Vec<usize>> {
+    struct Tarjan<'a> {
+        matrix: &'a [Vec<u64>],
+        nodes: &'a HashSet<usize>,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+
+            for w in 0..self.matrix.len() {
+                if self.matrix[v][w] == 0 || !self.nodes.contains(&w) {
+                    continue;
+                }
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    Some(_) => {}
+                }
+            }
+
+            if Some(self.low[v]) == self.index[v] {
+                let mut component = Vec::new();
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                self.components.push(component);
+            }
+        }
+    }
+
+    let set = nodes.iter().copied().collect::<HashSet<_>>();
+    let mut tarjan = Tarjan {
+        matrix,
+        nodes: &set,
+        index: vec![None; matrix.len()],
+        low: vec![0; matrix.len()],
+        on_stack: vec![false; matrix.len()],
+        stack: Vec::new(),
+        next: 0,
+        components: Vec::new(),
+    };
+
+    for &v in nodes {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+
+    tarjan.components
+}
+
+fn is_cyclic(matrix: &[Vec<u64>], component: &[usize]) -> bool {
+    component.len() > 1 || matrix[component[0]][component[0]] > 0
+}
+
+/// A component grows on its own unless it is a simple cycle, in which every
+/// token produces exactly one token of the component.
+fn is_expanding(matrix: &[Vec<u64>], component: &[usize]) -> bool {
+    is_cyclic(matrix, component)
+        && component.iter().any(|&i| component.iter().map(|&j| matrix[i][j]).sum::<u64>() > 1)
+}
+
+fn classify(matrix: &[Vec<u64>], reachable: &[usize]) -> Growth {
+    let components = components(matrix, reachable);
+
+    if components.iter().any(|c| is_expanding(matrix, c)) {
+        return Growth::Exponential;
+    }
+
+    // Without expanding components, lengths grow polynomially with a degree
+    // one less than the longest chain of cycles feeding into each other.
+    let mut component_of = vec![usize::MAX; matrix.len()];
+    for (index, component) in components.iter().enumerate() {
+        for &i in component {
+            component_of[i] = index;
+        }
+    }
+
+    // Components come in reverse topological order, so successors are done first.
+    let mut chain = vec![0usize; components.len()];
+    for (index, component) in components.iter().enumerate() {
+        let longest = component.iter()
+            .flat_map(|&i| (0..matrix.len()).filter(move |&j| matrix[i][j] > 0))
+            .map(|j| component_of[j])
+            .filter(|&c| c != index && c != usize::MAX)
+            .map(|c| chain[c])
+            .max()
+            .unwrap_or(0);
+
+        chain[index] = longest + usize::from(is_cyclic(matrix, component));
+    }
+
+    match chain.into_iter().max().unwrap_or(0) {
+        0 | 1 => Growth::Bounded,
+        longest => Growth::Polynomial(longest - 1),
+    }
+}
+
+fn dominant_eigenvalue(matrix: &[Vec<u64>], reachable: &[usize]) -> f64 {
+    // Iterating with `A + I` shifts every eigenvalue by one, which keeps the
+    // iteration from oscillating on periodic systems.
+    let mut vector = vec![1.0; reachable.len()];
+    let mut estimate = 0.0;
+
+    for _ in 0..POWER_ITERATIONS {
+        let next = reachable.iter()
+            .zip(&vector)
+            .map(|(&i, own)| {
+                let row = reachable.iter()
+                    .zip(&vector)
+                    .map(|(&j, v)| matrix[i][j] as f64 * v)
+                    .sum::<f64>();
+                row + own
+            })
+            .collect::<Vec<_>>();
+
+        let norm = next.iter().sum::<f64>();
+        let previous = vector.iter().sum::<f64>();
+        let next_estimate = norm / previous;
+
+        vector = next.into_iter().map(|v| v / norm).collect();
+
+        if (next_estimate - estimate).abs() < 1e-12 * next_estimate {
+            estimate = next_estimate;
+            break;
+        }
+        estimate = next_estimate;
+    }
+
+    estimate - 1.0
+}
+
+/// Recurrence from the characteristic polynomial of the growth matrix,
+/// computed with the Faddeev–LeVerrier algorithm.
+fn recurrence(matrix: &[Vec<u64>], reachable: &[usize]) -> Option<Vec<i128>> {
+    let n = reachable.len();
+    let a = reachable.iter()
+        .map(|&i| reachable.iter().map(|&j| matrix[i][j] as i128).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    // coefficients[k] is the coefficient of λ^k.
+    let mut coefficients = vec![0i128; n + 1];
+    coefficients[n] = 1;
+    let mut m = vec![vec![0i128; n]; n];
+
+    for k in 1..=n {
+        // M_k = A * M_{k-1} + c_{n-k+1} * I
+        let mut next = vec![vec![0i128; n]; n];
+        for i in 0..n {
+            for j in 0..n {
+                let mut sum = 0i128;
+                for l in 0..n {
+                    sum = sum.checked_add(a[i][l].checked_mul(m[l][j])?)?;
+                }
+                next[i][j] = sum;
+            }
+            next[i][i] = next[i][i].checked_add(coefficients[n - k + 1])?;
+        }
+        m = next;
+
+        // c_{n-k} = -tr(A * M_k) / k
+        let mut trace = 0i128;
+        for i in 0..n {
+            for l in 0..n {
+                trace = trace.checked_add(a[i][l].checked_mul(m[l][i])?)?;
+            }
+        }
+        coefficients[n - k] = -(trace / k as i128);
+    }
+
+    Some((1..=n).map(|j| -coefficients[n - j]).collect())
+}
+
+fn frequencies(matrix: &[Vec<u64>], axiom: &[TokenId]) -> Vec<f64> {
+    let n = matrix.len();
+    let mut counts = vec![0.0; n];
+    for id in axiom {
+        counts[id.value() as usize] += 1.0;
+    }
+
+    let mut average = vec![0.0; n];
+
+    for iteration in 0..FREQUENCY_ITERATIONS {
+        let total = counts.iter().sum::<f64>();
+        if total == 0.0 {
+            return vec![0.0; n];
+        }
+        counts.iter_mut().for_each(|c| *c /= total);
+
+        // Average the second half to smooth out periodic behaviour.
+        if iteration >= FREQUENCY_ITERATIONS / 2 {
+            for (average, c) in average.iter_mut().zip(&counts) {
+                *average += c / (FREQUENCY_ITERATIONS / 2) as f64;
+            }
+        }
+
+        let mut next = vec![0.0; n];
+        for (i, c) in counts.iter().enumerate() {
+            if *c == 0.0 {
+                continue;
+            }
+            for (j, next) in next.iter_mut().enumerate() {
+                *next += c * matrix[i][j] as f64;
+            }
+        }
+        counts = next;
+    }
+
+    average
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, LSystemBuilder};
+
+    #[test]
+    fn analyze_algae() -> Result<(), LSystemError> {
+        let analysis = analyze(&fixtures::algae()?)?;
+
+        let golden = (1.0 + 5f64.sqrt()) / 2.0;
+
+        assert_eq!(analysis.matrix, vec![vec![1, 1], vec![1, 0]]);
+        assert_eq!(analysis.growth, Growth::Exponential);
+        assert_eq!(analysis.recurrence, Some(vec![1, 1]));
+        assert!((analysis.dominant_eigenvalue - golden).abs() < 1e-9);
+        assert!((analysis.frequencies[0] - 1.0 / golden).abs() < 1e-6);
+        assert!((analysis.frequencies[1] - 1.0 / (golden * golden)).abs() < 1e-6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_polynomial_and_bounded() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let f = builder.token("F")?;
+        let x = builder.token("X")?;
+        let y = builder.token("Y")?;
+
+        // A leaves an F behind every step, which then stays: linear growth.
+        builder.axiom(vec![a])?;
+        builder.production_rule(a, vec![f, a])?;
+        builder.production_rule(x, vec![y])?;
+        builder.production_rule(y, vec![x])?;
+
+        let system = builder.clone().finish()?;
+        let analysis = analyze(&system)?;
+        assert_eq!(analysis.growth, Growth::Polynomial(1));
+        assert_eq!(analysis.dominant_eigenvalue, 1.0);
+        assert!((analysis.frequencies[1] - 1.0).abs() < 1e-2);
+
+        // X and Y flip forever, but never grow.
+        builder.axiom(vec![x])?;
+        let analysis = analyze(&builder.finish()?)?;
+        assert_eq!(analysis.growth, Growth::Bounded);
+        assert_eq!(analysis.recurrence, Some(vec![0, 1]));
+        assert!((analysis.frequencies[2] - 0.5).abs() < 1e-9);
+
+        Ok(())
+    }
+
+    #[test]
+    fn recurrence_keeps_trailing_zeros() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+
+        builder.axiom(vec![a])?;
+        builder.production_rule(a, vec![b, b])?;
+
+        // Lengths are 1, 2, 2, ..., so `len(n) = len(n - 1)` only from `n = 2`.
+        let analysis = analyze(&builder.finish()?)?;
+        assert_eq!(analysis.recurrence, Some(vec![1, 0]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_counts_by_squaring() -> Result<(), LSystemError> {
+        let mut system = fixtures::algae()?;
+
+        assert_eq!(token_counts(&system, 0)?, vec![BigUint::from(1u32), BigUint::ZERO]);
+
+        system.step_by(20);
+        let a = system.get_state().iter().filter(|id| id.value() == 0).count();
+        let b = system.get_state().len() - a;
+        assert_eq!(token_counts(&system, 20)?, vec![BigUint::from(a), BigUint::from(b)]);
+
+        // Fibonacci numbers F(201) and F(200), far beyond `u128`.
+        let counts = system.token_counts(200)?;
+        assert_eq!(counts[0].to_string(), "453973694165307953197296969697410619233826");
+        assert_eq!(counts[1].to_string(), "280571172992510140037611932413038677189525");
+
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_requires_determinism() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        builder.axiom(vec![a])?;
+        builder.production_fn(a, |_| vec![])?;
+
+        assert!(matches!(analyze(&builder.finish()?), Err(LSystemError::NotDeterministic)));
+
+        Ok(())
+    }
+}
@@ -1,24 +1,37 @@
 use std::slice::Iter;
 use array_init::array_init;
 
-use crate::token::{Token, TokenId};
+use crate::errors::LSystemError;
+use crate::token::{ArenaId, Token, TokenHandle, TokenId};
 
-#[derive(Debug, Clone)]
+/// Tokens of an L-system. Every arena brands the handles it hands out, and
+/// only accepts its own handles. A clone gets a brand of its own: it accepts
+/// the handles created before it was cloned, but not those either arena
+/// creates afterwards.
+#[derive(Debug)]
 pub struct Arena {
+    id: ArenaId,
     token: [Token; 128],
-    rules: [(); 128],
     len: u8,
+    /// Brands of the arenas this one was cloned from, with their length at
+    /// the time.
+    ancestors: Vec<(ArenaId, u8)>,
 }
 
 impl Arena {
     pub fn new() -> Self {
         Self {
+            id: ArenaId::next(),
             token: array_init(|_| Token::default()),
-            rules: [(); 128],
             len: 0,
+            ancestors: Vec::new(),
         }
     }
 
+    pub fn id(&self) -> ArenaId {
+        self.id
+    }
+
     pub fn len(&self) -> u8 {
         self.len
     }
@@ -28,7 +41,11 @@ impl Arena {
     }
 
     pub fn get_token(&self, id: &TokenId) -> Option<&Token> {
-        self.token.get(id.0 as usize)
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        self.token.get(id.value() as usize)
     }
 
     pub fn iter_tokens(&self) -> Iter<'_, Token> {
@@ -36,29 +53,102 @@ impl Arena {
         self.token[..self.len as usize].iter()
     }
 
+    /// Whether `id` is a token of this arena, flagged as taking parameters
+    /// exactly when the token does.
     pub fn is_valid(&self, id: &TokenId) -> bool {
-        id.value() < self.len
+        self.is_flagged(id, self.len)
+    }
+
+    /// Whether `id` is among the first `len` tokens, with the parameter flag
+    /// of the token.
+    fn is_flagged(&self, id: &TokenId, len: u8) -> bool {
+        id.value() < len && *id == TokenId::new(id.value(), self.token[id.value() as usize].param() > 0)
     }
 
     pub fn is_valid_slice(&self, slice: &[TokenId]) -> bool {
         slice.iter().all(|id| self.is_valid(id))
     }
 
-    pub fn push_token(&mut self, value: Token) -> TokenId {
+    /// Whether `handle` was created by this arena, or by the arena it was
+    /// cloned from before the clone.
+    pub fn owns(&self, handle: &TokenHandle) -> bool {
+        self.limit(handle.arena())
+            .is_some_and(|limit| self.is_flagged(&handle.id(), limit))
+    }
+
+    /// How many tokens handles branded with `brand` may refer to, if it is
+    /// the brand of this arena or of one it was cloned from.
+    fn limit(&self, brand: ArenaId) -> Option<u8> {
+        if brand == self.id {
+            return Some(self.len);
+        }
+
+        self.ancestors
+            .iter()
+            .find(|(ancestor, _)| *ancestor == brand)
+            .map(|&(_, len)| len)
+    }
+
+    /// Brands `id`, e.g. one read from a state, with this arena. Returns
+    /// `None` if it isn't a token of this arena.
+    ///
+    /// Tokens this arena inherited from the one it was cloned from keep the
+    /// brand of the arena that created them, so that their handles equal
+    /// those handed out before the clone.
+    pub fn handle(&self, id: TokenId) -> Option<TokenHandle> {
+        self.is_valid(&id).then(|| self.brand(id))
+    }
+
+    /// Brands `id` with the oldest arena, among this one and those it was
+    /// cloned from, that already had it.
+    pub(crate) fn brand(&self, id: TokenId) -> TokenHandle {
+        let brand = self.ancestors
+            .iter()
+            .find(|&&(_, len)| id.value() < len)
+            .map_or(self.id, |&(ancestor, _)| ancestor);
+
+        TokenHandle::new(id, brand)
+    }
+
+    /// Explains why `handle` isn't owned by this arena.
+    pub(crate) fn invalid_id(&self, handle: TokenHandle) -> LSystemError {
+        match self.limit(handle.arena()) {
+            // Branded by this arena, but with the wrong parameter flag.
+            Some(limit) if handle.value() < limit => LSystemError::MisflaggedTokenId(handle.id()),
+            _ if handle.arena() == ArenaId::NONE => LSystemError::UnbrandedToken(handle.id()),
+            _ => LSystemError::ForeignTokenId(handle.id()),
+        }
+    }
+
+    pub fn push_token(&mut self, value: Token) -> TokenHandle {
         let id = TokenId::new(self.len, value.param() > 0);
-        self.token[id.0 as usize] = value;
+        self.token[id.value() as usize] = value;
         self.len += 1;
 
-        id
+        TokenHandle::new(id, self.id)
     }
 
-    pub fn enumerate(&self) -> EnumerableArena {
+    pub fn enumerate(&self) -> EnumerableArena<'_> {
         EnumerableArena {
             inner: self.iter_tokens().enumerate(),
         }
     }
 }
 
+impl Clone for Arena {
+    fn clone(&self) -> Self {
+        let mut ancestors = self.ancestors.clone();
+        ancestors.push((self.id, self.len));
+
+        Self {
+            id: ArenaId::next(),
+            token: self.token.clone(),
+            len: self.len,
+            ancestors,
+        }
+    }
+}
+
 pub struct EnumerableArena<'a> {
     inner: std::iter::Enumerate<Iter<'a, Token>>,
 }
@@ -68,7 +158,7 @@ impl<'a> Iterator for EnumerableArena<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let (index, t) = self.inner.next()?;
-        Some((TokenId(index as u8), t))
+        Some((TokenId::new(index as u8, t.param() > 0), t))
     }
 }
 
@@ -89,14 +179,14 @@ mod tests {
         let a = arena.push_token("Hello!".into());
         let b = arena.push_token("World".into());
 
-        assert_eq!(a.0, 0);
-        assert_eq!(b.0, 1);
+        assert_eq!(a.id().0, 0);
+        assert_eq!(b.id().0, 1);
         assert_eq!(arena.len(), 2);
 
-        let a_ref = arena.get_token(&a).expect("Failed to get a");
+        let a_ref = arena.get_token(&a.id()).expect("Failed to get a");
 
         assert_eq!(*a_ref, "Hello!".into());
-        assert_eq!(arena.get_token(&b).unwrap(), &"World".into());
+        assert_eq!(arena.get_token(&b.id()).unwrap(), &"World".into());
     }
 
     #[test]
@@ -126,9 +216,87 @@ mod tests {
 
         let mut enumerator = arena.enumerate();
 
-        assert_eq!(enumerator.next(), Some((a, &"first".into())));
-        assert_eq!(enumerator.next(), Some((b, &"second".into())));
-        assert_eq!(enumerator.next(), Some((c, &"third".into())));
-        assert_eq!(enumerator.next(), Some((d, &"fourth".into())));
+        assert_eq!(enumerator.next(), Some((a.id(), &"first".into())));
+        assert_eq!(enumerator.next(), Some((b.id(), &"second".into())));
+        assert_eq!(enumerator.next(), Some((c.id(), &"third".into())));
+        assert_eq!(enumerator.next(), Some((d.id(), &"fourth".into())));
+    }
+
+    #[test]
+    fn arena_module_ids() {
+        let mut arena = Arena::new();
+
+        let a = arena.push_token("A".into());
+        let f = arena.push_token(Token::with_arity("F", 2).unwrap());
+
+        // Ids of modules carry the parameter flag, both when pushed and when
+        // enumerated, and are looked up by their value.
+        assert!(f.has_param());
+        assert_eq!(arena.enumerate().map(|(id, _)| id).collect::<Vec<_>>(), vec![a.id(), f.id()]);
+        assert_eq!(arena.get_token(&f.id()).map(Token::name), Some("F"));
+    }
+
+    #[test]
+    fn arena_rejects_foreign_ids() {
+        let mut arena = Arena::new();
+        let mut other = Arena::new();
+
+        let a = arena.push_token("A".into());
+        let b = other.push_token("B".into());
+
+        assert_eq!(a.value(), b.value());
+        assert!(arena.owns(&a));
+        assert!(!arena.owns(&b));
+        assert!(!arena.owns(&TokenId::new(0, false).into()));
+        assert_eq!(arena.handle(a.id()), Some(a));
+        assert_eq!(arena.handle(TokenId::new(1, false)), None);
+    }
+
+    #[test]
+    fn arena_rejects_misflagged_ids() {
+        let mut arena = Arena::new();
+
+        let a = arena.push_token("A".into());
+        let f = arena.push_token(Token::with_arity("F", 2).unwrap());
+
+        let a_with_param = TokenId::new(a.value(), true);
+        let f_without_param = TokenId::new(f.value(), false);
+
+        assert!(!arena.is_valid(&a_with_param));
+        assert!(!arena.is_valid(&f_without_param));
+        assert_eq!(arena.get_token(&a_with_param), None);
+        assert_eq!(arena.handle(a_with_param), None);
+        assert_eq!(arena.handle(f_without_param), None);
+        assert!(!arena.owns(&TokenHandle::new(a_with_param, arena.id())));
+        assert!(!arena.clone().owns(&TokenHandle::new(f_without_param, arena.id())));
+    }
+
+    #[test]
+    fn arena_clone_gets_its_own_brand() {
+        let mut arena = Arena::new();
+        let a = arena.push_token("A".into());
+
+        let mut clone = arena.clone();
+        assert_ne!(clone.id(), arena.id());
+        assert!(clone.owns(&a));
+
+        // Tokens added after cloning belong to one arena only, although
+        // they share the same value.
+        let b = arena.push_token("B".into());
+        let x = clone.push_token("X".into());
+        assert_eq!(b.value(), x.value());
+        assert!(!clone.owns(&b));
+        assert!(!arena.owns(&x));
+
+        // Clones of clones still accept the handles they inherited.
+        let grandchild = clone.clone();
+        assert!(grandchild.owns(&a));
+        assert!(grandchild.owns(&x));
+        assert!(!grandchild.owns(&b));
+
+        // Ids read back keep the brand of the arena that created them.
+        assert_eq!(grandchild.handle(a.id()), Some(a));
+        assert_eq!(grandchild.handle(x.id()), Some(x));
+        assert_ne!(x, b);
     }
 }
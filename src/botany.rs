@@ -0,0 +1,168 @@
+use crate::branching::BranchTree;
+
+/// Architecture of a branching structure, as measured on plants, see
+/// [`report`]. Per-branch values follow the order of
+/// [`BranchTree::branches`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotanicalReport {
+    /// Horton–Strahler order of every branch, at its base; `0` for branches
+    /// holding no tokens at all.
+    pub strahler_orders: Vec<usize>,
+    /// Branching order of every branch: `1` for the main axis, `2` for the
+    /// branches it bears, and so on.
+    pub branching_orders: Vec<usize>,
+    /// Number of streams of each Horton–Strahler order, starting at order `1`.
+    pub strahler_counts: Vec<usize>,
+    /// Ratio of the number of streams of each Horton–Strahler order to the
+    /// number of streams of the next one.
+    pub bifurcation_ratios: Vec<f64>,
+    /// The deepest nesting of branches.
+    pub max_depth: usize,
+    /// Number of branches ending in an apex, i.e. holding tokens of their own.
+    pub apices: usize,
+}
+
+/// Measures the branches of `tree`.
+///
+/// Each branch is an axis bearing its sub-branches along the way. For the
+/// Horton–Strahler order, the axis is split into segments at every
+/// sub-branch: its apex has order `1`, and walking down to the base, where
+/// segments and sub-branches meet, the highest order is kept, raised by one
+/// if it meets itself. Sub-branches with no tokens of the axis between them
+/// meet at the same point. A stream is a maximal run of segments of equal
+/// order, so a new one starts at every apex and every raise.
+pub fn report(tree: &BranchTree) -> BotanicalReport {
+    let branches = tree.branches();
+
+    // Sub-branches always come after their parent.
+    let mut strahler_orders = vec![0; branches.len()];
+    let mut strahler_counts = Vec::new();
+    for index in (0..branches.len()).rev() {
+        strahler_orders[index] = axis_order(tree, index, &strahler_orders, &mut strahler_counts);
+    }
+
+    let bifurcation_ratios = strahler_counts
+        .windows(2)
+        .map(|counts| counts[0] as f64 / counts[1] as f64)
+        .collect();
+
+    let apices = branches
+        .iter()
+        .filter(|branch| {
+            let nested = branch.children.iter().map(|child| branches[*child].size() + 2).sum::<usize>();
+            branch.size() > nested
+        })
+        .count();
+
+    BotanicalReport {
+        strahler_orders,
+        branching_orders: branches.iter().map(|branch| branch.depth + 1).collect(),
+        strahler_counts,
+        bifurcation_ratios,
+        max_depth: tree.max_depth(),
+        apices,
+    }
+}
+
+/// Walks the axis of the branch at `index` from its apex down to its base,
+/// counting the streams starting along it, and returns its order at the base.
+fn axis_order(tree: &BranchTree, index: usize, orders: &[usize], counts: &mut Vec<usize>) -> usize {
+    let branch = &tree.branches()[index];
+    let mut start_stream = |order: usize| {
+        if counts.len() < order {
+            counts.resize(order, 0);
+        }
+        counts[order - 1] += 1;
+    };
+
+    // The order of the segment above the current point, and the orders of
+    // the sub-branches attached to it.
+    let mut order = 0;
+    let mut attached = Vec::new();
+    let mut children = branch.children.iter().rev();
+    let mut position = branch.range.end;
+
+    while position > branch.range.start {
+        position -= 1;
+
+        // Brackets on the axis close its sub-branches, last one first.
+        if let Some(open) = tree.matching(position) {
+            let child = children.next().expect("brackets in a branch enclose its sub-branches");
+            attached.push(orders[*child]);
+            position = open;
+            continue;
+        }
+
+        if !attached.is_empty() {
+            order = meet(order, &mut attached, &mut start_stream);
+        }
+        if order == 0 {
+            order = 1;
+            start_stream(order);
+        }
+    }
+
+    if !attached.is_empty() {
+        order = meet(order, &mut attached, &mut start_stream);
+    }
+
+    order
+}
+
+/// The order where a segment of `order` meets sub-branches of orders
+/// `attached`, starting a stream if it is raised. Clears `attached`.
+fn meet(order: usize, attached: &mut Vec<usize>, start_stream: &mut impl FnMut(usize)) -> usize {
+    attached.push(order);
+    let highest = attached.iter().copied().max().unwrap_or(0);
+    let meeting = attached.iter().filter(|other| **other == highest).count();
+    attached.clear();
+
+    if highest > 0 && meeting > 1 {
+        start_stream(highest + 1);
+        highest + 1
+    } else {
+        highest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, LSystemError};
+
+    #[test]
+    fn report_of_binary_tree() -> Result<(), LSystemError> {
+        let mut system = fixtures::binary_tree()?;
+        system.step_by(2);
+        assert_eq!(system.render(), "11[1[0]0]1[0]0");
+
+        let metrics = report(&system.branch_tree()?);
+        assert_eq!(metrics.strahler_orders, [3, 2, 1, 1]);
+        assert_eq!(metrics.branching_orders, [1, 2, 3, 2]);
+        assert_eq!(metrics.strahler_counts, [4, 2, 1]);
+        assert_eq!(metrics.bifurcation_ratios, [2.0, 2.0]);
+        assert_eq!(metrics.max_depth, 2);
+        assert_eq!(metrics.apices, 4);
+
+        // An empty branch has no apex.
+        system.remove_range(11..12);
+        assert_eq!(system.render(), "11[1[0]0]1[]0");
+        assert_eq!(report(&system.branch_tree()?).apices, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_counts_streams_of_perfect_binary_tree() -> Result<(), LSystemError> {
+        let mut system = fixtures::binary_tree()?;
+        system.step_by(3);
+
+        let metrics = report(&system.branch_tree()?);
+        assert_eq!(metrics.apices, 8);
+        assert_eq!(metrics.strahler_orders[0], 4);
+        assert_eq!(metrics.strahler_counts, [8, 4, 2, 1]);
+        assert_eq!(metrics.bifurcation_ratios, [2.0, 2.0, 2.0]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::ops::Range;
+
+use crate::token::TokenId;
+
+/// A branch of a bracketed state: the tokens between an opening bracket and
+/// its matching closing bracket, or the whole state for the main axis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// Indices of the state covered by the branch, brackets excluded and
+    /// sub-branches included.
+    pub range: Range<usize>,
+    pub parent: Option<usize>,
+    /// The sub-branches attached to this branch, in order.
+    pub children: Vec<usize>,
+    /// The number of branches enclosing this one; `0` for the main axis.
+    pub depth: usize,
+}
+
+impl Branch {
+    /// The index of the opening bracket of the branch, `None` for the main axis.
+    pub fn open(&self) -> Option<usize> {
+        self.parent.map(|_| self.range.start - 1)
+    }
+
+    /// The number of tokens in the branch and its sub-branches.
+    pub fn size(&self) -> usize {
+        self.range.len()
+    }
+
+    /// Whether no branch is attached to this one.
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+/// The branches of a bracketed state as a tree, see [`LSystem::branch_tree`].
+/// Branch `0` is the main axis; every other branch comes after its parent.
+///
+/// [`LSystem::branch_tree`]: crate::LSystem::branch_tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchTree {
+    branches: Vec<Branch>,
+    matching: Vec<Option<usize>>,
+}
+
+impl BranchTree {
+    /// Builds the tree of `state` in linear time, given the tokens opening
+    /// and closing a branch, as returned by [`LSystem::branch_tokens`]. Fails
+    /// with the index of the first bracket without a match.
+    ///
+    /// [`LSystem::branch_tokens`]: crate::LSystem::branch_tokens
+    pub fn parse(state: &[TokenId], branches: Option<(TokenId, TokenId)>) -> Result<Self, usize> {
+        let (open, close) = branches.unzip();
+        let matching = matching_brackets(state, open, close)?;
+
+        let mut branches = vec![Branch {
+            range: 0..state.len(),
+            parent: None,
+            children: Vec::new(),
+            depth: 0,
+        }];
+        let mut stack = vec![0];
+
+        for (index, id) in state.iter().enumerate() {
+            if Some(*id) == open {
+                let parent = *stack.last().expect("the main axis is never closed");
+                let branch = branches.len();
+
+                branches.push(Branch {
+                    range: index + 1..matching[index].expect("brackets are balanced"),
+                    parent: Some(parent),
+                    children: Vec::new(),
+                    depth: stack.len(),
+                });
+                branches[parent].children.push(branch);
+                stack.push(branch);
+            } else if Some(*id) == close {
+                stack.pop();
+            }
+        }
+
+        Ok(Self { branches, matching })
+    }
+
+    /// The main axis, which covers the whole state.
+    pub fn root(&self) -> &Branch {
+        &self.branches[0]
+    }
+
+    pub fn branch(&self, index: usize) -> Option<&Branch> {
+        self.branches.get(index)
+    }
+
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    /// The number of branches, the main axis included.
+    pub fn len(&self) -> usize {
+        self.branches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.branches.is_empty()
+    }
+
+    /// Returns the index of the bracket matching the one at `index` of the
+    /// state, or `None` if it isn't a bracket.
+    pub fn matching(&self, index: usize) -> Option<usize> {
+        self.matching.get(index).copied().flatten()
+    }
+
+    /// The deepest nesting of branches.
+    pub fn max_depth(&self) -> usize {
+        self.branches.iter().map(|branch| branch.depth).max().unwrap_or(0)
+    }
+
+    /// Iterates over the branches without sub-branches.
+    pub fn leaves(&self) -> impl Iterator<Item = &Branch> {
+        self.branches.iter().filter(|branch| branch.is_leaf())
+    }
+}
+
+/// Pairs every bracket of `state` with its match in a single pass.
+fn matching_brackets(
+    state: &[TokenId],
+    open: Option<TokenId>,
+    close: Option<TokenId>,
+) -> Result<Vec<Option<usize>>, usize> {
+    let mut matching = vec![None; state.len()];
+    let mut stack = Vec::new();
+
+    for (index, id) in state.iter().enumerate() {
+        if Some(*id) == open {
+            stack.push(index);
+        } else if Some(*id) == close {
+            let start = stack.pop().ok_or(index)?;
+            matching[start] = Some(index);
+            matching[index] = Some(start);
+        }
+    }
+
+    match stack.first() {
+        Some(&unclosed) => Err(unclosed),
+        None => Ok(matching),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, LSystemBuilder, LSystemError};
+
+    #[test]
+    fn branch_tree_of_binary_tree() -> Result<(), LSystemError> {
+        let mut system = fixtures::binary_tree()?;
+        system.step_by(2);
+        assert_eq!(system.render(), "11[1[0]0]1[0]0");
+
+        let tree = system.branch_tree()?;
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.root().children, [1, 3]);
+        assert_eq!(tree.branch(1).unwrap().children, [2]);
+        assert_eq!(tree.branch(2).unwrap().range, 5..6);
+        assert_eq!(tree.branch(2).unwrap().depth, 2);
+        assert_eq!(tree.branch(1).unwrap().size(), 5);
+        assert_eq!(tree.max_depth(), 2);
+        assert_eq!(tree.leaves().count(), 2);
+
+        assert_eq!(tree.matching(2), Some(8));
+        assert_eq!(tree.matching(8), Some(2));
+        assert_eq!(tree.matching(0), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn branch_tree_of_unbalanced_state() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+        let close = builder.token("]")?;
+        let open = builder.token("[")?;
+        builder.branch_tokens(open, close)?;
+        builder.axiom(vec![a])?;
+
+        let mut system = builder.finish()?;
+        system.insert_at(1, &[close])?;
+
+        assert!(matches!(system.branch_tree(), Err(LSystemError::UnmatchedBracket(1))));
+
+        Ok(())
+    }
+}
@@ -1,31 +1,100 @@
 use std::collections::HashMap;
-use lsystem::token::TokenId;
+use std::sync::Arc;
+use lsystem::token::{TokenHandle, TokenId};
 
 use crate::arena::{Arena};
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+use crate::environment::{ModuleKind, QUERY_ARITY};
 use crate::errors::LSystemError;
+use crate::grammar::Grammar;
+use crate::lint::{self, LintWarning};
+use crate::production::{Context, ProductionFn, Successor};
 use crate::system::LSystem;
 use crate::token::Token;
+use crate::turtle::Turtle;
 
 #[derive(Debug, Clone)]
-struct ProductionRule {
-    predecessor: TokenId,
-    successor: Vec<TokenId>,
+pub(crate) struct ProductionRule {
+    pub(crate) location: Location,
+    pub(crate) predecessor: TokenId,
+    pub(crate) successor: Successor,
 }
 
 impl ProductionRule {
-    pub fn new(predecessor: TokenId, successor: Vec<TokenId>) -> Self {
+    pub fn new(location: Location, predecessor: TokenId, successor: Successor) -> Self {
         Self {
+            location,
             predecessor,
             successor,
         }
     }
 }
 
-#[derive(Default, Clone)]
+/// Where in a builder a token, rule or axiom was defined. Tokens are numbered
+/// in the order they were declared and rules in the order they were
+/// submitted, production rules and decomposition rules separately. Rejected
+/// declarations and rules take a number too, so that numbers match the order
+/// of the calls that added them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    Token(usize),
+    BranchTokens,
+    Axiom,
+    Rule(usize),
+    Decomposition(usize),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Token(index) => write!(f, "token #{}", index),
+            Location::BranchTokens => write!(f, "branch tokens"),
+            Location::Axiom => write!(f, "axiom"),
+            Location::Rule(index) => write!(f, "rule #{}", index),
+            Location::Decomposition(index) => write!(f, "decomposition rule #{}", index),
+        }
+    }
+}
+
+/// Maximum number of decomposition passes applied after each step unless
+/// overridden with [`LSystemBuilder::max_decomposition_depth`].
+pub const DEFAULT_DECOMPOSITION_DEPTH: usize = 16;
+
+#[derive(Clone)]
 pub struct LSystemBuilder {
     arena: Arena,
     axiom: Option<Vec<TokenId>>,
     rules: Vec<ProductionRule>,
+    decompositions: Vec<ProductionRule>,
+    /// Tokens declared so far, accepted or not.
+    declared: usize,
+    /// Production and decomposition rules submitted so far, accepted or not.
+    submitted: (usize, usize),
+    decomposition_depth: usize,
+    turtle: Turtle,
+    seed: Option<u64>,
+    branches: Option<(TokenId, TokenId)>,
+    rejected: Vec<Diagnostic>,
+    digit_suffix_arity: bool,
+}
+
+impl Default for LSystemBuilder {
+    fn default() -> Self {
+        Self {
+            arena: Arena::default(),
+            axiom: None,
+            rules: Vec::new(),
+            decompositions: Vec::new(),
+            declared: 0,
+            submitted: (0, 0),
+            decomposition_depth: DEFAULT_DECOMPOSITION_DEPTH,
+            turtle: Turtle::default(),
+            seed: None,
+            branches: None,
+            rejected: Vec::new(),
+            digit_suffix_arity: false,
+        }
+    }
 }
 
 impl LSystemBuilder {
@@ -33,84 +102,520 @@ impl LSystemBuilder {
         Self::default()
     }
 
-    pub fn token<S: Into<String>>(&mut self, name: S) -> Result<TokenId, LSystemError> {
-        let token = Token::new(name)?;
-        Ok(self.arena.push_token(token))
+    pub fn token<S: Into<String>>(&mut self, name: S) -> Result<TokenHandle, LSystemError> {
+        let token = if self.digit_suffix_arity {
+            Token::with_inferred_arity(name)
+        } else {
+            Token::new(name)
+        };
+        self.declare(token)
+    }
+
+    /// Declares a module holding `arity` parameter values, which are filled in
+    /// after each step. Only query and communication modules hold values: the
+    /// query module has to be declared with [`QUERY_ARITY`], and any other
+    /// token with an arity of zero.
+    ///
+    /// [`QUERY_ARITY`]: crate::environment::QUERY_ARITY
+    pub fn module<S: Into<String>>(&mut self, name: S, arity: u8) -> Result<TokenHandle, LSystemError> {
+        self.declare(Token::with_arity(name, arity))
     }
 
-    fn validate_ids(&self, ids: &[TokenId]) -> Result<(), LSystemError> {
-        for &id in ids {
-            if !self.arena.is_valid(&id) {
-                return Err(LSystemError::InvalidTokenId(id));
+    /// Adds `token` to the arena, remembering why it was rejected otherwise.
+    fn declare(&mut self, token: Result<Token, LSystemError>) -> Result<TokenHandle, LSystemError> {
+        self.declared += 1;
+        let location = Location::Token(self.declared - 1);
+
+        match token {
+            Ok(token) => {
+                // Only communication modules hold as many values as declared.
+                let expected = match ModuleKind::of(&token) {
+                    Some(ModuleKind::Query) => QUERY_ARITY,
+                    Some(ModuleKind::Communication) => token.param(),
+                    None => 0,
+                };
+                if token.param() == expected {
+                    return Ok(self.arena.push_token(token));
+                }
+
+                let (expected, found) = (expected as usize, token.param() as usize);
+                self.rejected.push(Diagnostic::new(location, token.name(), DiagnosticKind::InvalidArity { expected, found }));
+                Err(LSystemError::ArityMismatch { name: token.name().to_string(), expected, found })
+            }
+            Err(error) => {
+                if let LSystemError::InvalidToken(name) = &error {
+                    self.rejected.push(Diagnostic::new(location, name.as_str(), DiagnosticKind::InvalidToken));
+                }
+                Err(error)
             }
         }
+    }
 
-        Ok(())
+    /// Makes [`LSystemBuilder::token`] infer the arity of tokens from the
+    /// trailing digits of their name, so that `?E2` takes two parameters. As
+    /// with [`LSystemBuilder::module`], tokens that aren't query or
+    /// communication modules are rejected if they end in digits other than
+    /// zeros.
+    pub fn digit_suffix_arity(&mut self, enabled: bool) {
+        self.digit_suffix_arity = enabled;
+    }
+
+    fn invalid_id(&self, handle: TokenHandle) -> LSystemError {
+        self.arena.invalid_id(handle)
+    }
+
+    /// Checks that every handle belongs to this builder, returning their ids.
+    /// Every problem is remembered for [`LSystemBuilder::finish_with_diagnostics`].
+    fn validate_ids_at(
+        &mut self,
+        location: Location,
+        handles: &[TokenHandle],
+        text: impl FnOnce(&Arena) -> String,
+    ) -> Result<Vec<TokenId>, LSystemError> {
+        let invalid = handles.iter()
+            .copied()
+            .filter(|handle| !self.arena.owns(handle))
+            .collect::<Vec<_>>();
+
+        let Some(&first) = invalid.first() else {
+            return Ok(handles.iter().map(TokenHandle::id).collect());
+        };
+
+        let text = text(&self.arena);
+        for handle in invalid {
+            let kind = match self.invalid_id(handle) {
+                LSystemError::ForeignTokenId(id) => DiagnosticKind::ForeignTokenId(id),
+                LSystemError::UnbrandedToken(id) => DiagnosticKind::UnbrandedToken(id),
+                LSystemError::MisflaggedTokenId(id) => DiagnosticKind::MisflaggedTokenId(id),
+                _ => DiagnosticKind::InvalidTokenId(handle.id()),
+            };
+            self.rejected.push(Diagnostic::new(location, text.as_str(), kind));
+        }
+
+        Err(self.invalid_id(first))
     }
 
     pub fn production_rule(
         &mut self,
-        predecessor: TokenId,
-        successor: Vec<TokenId>,
+        predecessor: TokenHandle,
+        successor: Vec<TokenHandle>,
     ) -> Result<(), LSystemError> {
-        // Verify that all provided TokenId's correspond to a token in this LSystem.
-        self.validate_ids(&[predecessor])?;
-        self.validate_ids(&successor)?;
+        let location = self.submit_rule();
+
+        // Verify that all provided handles correspond to a token in this LSystem.
+        let mut ids = self.validate_ids_at(location, &[&[predecessor][..], &successor].concat(), |arena| {
+            render_handle_rule(arena, predecessor, &successor)
+        })?;
+        let successor = ids.split_off(1);
 
         // Add the rule to this system
         self.rules
-            .push(ProductionRule::new(predecessor, successor));
+            .push(ProductionRule::new(location, ids[0], Successor::Tokens(successor)));
+
+        Ok(())
+    }
+
+    /// Numbers the next production rule.
+    fn submit_rule(&mut self) -> Location {
+        self.submitted.0 += 1;
+        Location::Rule(self.submitted.0 - 1)
+    }
+
+    /// Adds a production whose successor is computed by `f` each time `predecessor`
+    /// is rewritten. The returned handles are validated when the system is stepped.
+    pub fn production_fn<F>(&mut self, predecessor: TokenHandle, f: F) -> Result<(), LSystemError>
+    where
+        F: Fn(&Context) -> Vec<TokenHandle> + Send + Sync + 'static,
+    {
+        let location = self.submit_rule();
+        let ids = self.validate_ids_at(location, &[predecessor], |arena| {
+            format!("{} => <fn>", render_handles(arena, &[predecessor]))
+        })?;
+
+        self.rules
+            .push(ProductionRule::new(location, ids[0], Successor::Function(ProductionFn::new(f))));
+
+        Ok(())
+    }
+
+    /// Adds a decomposition rule. Decomposition rules are applied after every
+    /// step, repeatedly, until none of them match or the maximum depth is reached.
+    pub fn decomposition_rule(
+        &mut self,
+        predecessor: TokenHandle,
+        successor: Vec<TokenHandle>,
+    ) -> Result<(), LSystemError> {
+        self.submitted.1 += 1;
+        let location = Location::Decomposition(self.submitted.1 - 1);
+        let mut ids = self.validate_ids_at(location, &[&[predecessor][..], &successor].concat(), |arena| {
+            render_handle_rule(arena, predecessor, &successor)
+        })?;
+        let successor = ids.split_off(1);
+
+        self.decompositions
+            .push(ProductionRule::new(location, ids[0], Successor::Tokens(successor)));
+
+        Ok(())
+    }
+
+    /// Sets the maximum number of decomposition passes applied after each step.
+    pub fn max_decomposition_depth(&mut self, depth: usize) {
+        self.decomposition_depth = depth;
+    }
+
+    /// Declares the tokens opening and closing a branch. Once declared, `finish`
+    /// rejects axioms and successors in which they don't balance, and rules
+    /// that rewrite a branch token into anything but itself. The successors
+    /// of production functions are checked when the system is stepped.
+    pub fn branch_tokens(&mut self, open: TokenHandle, close: TokenHandle) -> Result<(), LSystemError> {
+        self.validate_ids_at(Location::BranchTokens, &[open, close], |arena| {
+            render_handles(arena, &[open, close])
+        })?;
+        self.branches = Some((open.id(), close.id()));
+
+        Ok(())
+    }
+
+    /// Locations of the axiom and rules whose branch tokens don't balance,
+    /// including rules rewriting a branch token into anything but itself.
+    fn unbalanced(&self) -> Vec<Location> {
+        let Some((open, close)) = self.branches else {
+            return Vec::new();
+        };
+
+        let axiom = self.axiom
+            .as_deref()
+            .filter(|axiom| !is_balanced(axiom, open, close))
+            .map(|_| Location::Axiom);
+
+        let rules = self.locations()
+            .filter(|(_, rule)| {
+                let successor = rule.successor.tokens();
+                if rule.predecessor == open || rule.predecessor == close {
+                    return successor != Some(&[rule.predecessor][..]);
+                }
+
+                successor.is_some_and(|successor| !is_balanced(successor, open, close))
+            })
+            .map(|(location, _)| location);
+
+        axiom.into_iter().chain(rules).collect()
+    }
+
+    /// Seeds the random number generator exposed to production functions.
+    /// Without a seed, a random one is picked when the system is built.
+    pub fn seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    /// Sets the turtle used to fill the parameters of query and communication modules.
+    pub fn turtle(&mut self, turtle: Turtle) {
+        self.turtle = turtle;
+    }
+
+    pub fn axiom(&mut self, axiom: Vec<TokenHandle>) -> Result<(), LSystemError> {
+        let axiom = self.validate_ids_at(Location::Axiom, &axiom, |arena| render_handles(arena, &axiom))?;
+        self.axiom = Some(axiom);
+
+        Ok(())
+    }
+
+    /// Adds a production rule written as text, e.g. `A => F[+A]`. Token names
+    /// are matched greedily against the tokens declared so far; whitespace
+    /// between them is ignored. Query and communication modules may be followed
+    /// by placeholders for the values they are filled with after each step,
+    /// e.g. `?P(x, y)`, as many as their arity. Parameters can't be written
+    /// for other tokens, as the system keeps no values for them.
+    pub fn parse_rule(&mut self, rule: &str) -> Result<(), LSystemError> {
+        let location = self.submit_rule();
+
+        let Some((predecessor, successor)) = rule.split_once("=>") else {
+            self.rejected.push(Diagnostic::new(location, rule.trim(), DiagnosticKind::InvalidRule));
+            return Err(LSystemError::InvalidRule(rule.trim().to_string()));
+        };
+
+        let predecessor = self.parse_tokens(location, predecessor, rule)?;
+        let successor = self.parse_tokens(location, successor, rule)?;
+
+        let [predecessor] = predecessor[..] else {
+            self.rejected.push(Diagnostic::new(location, rule.trim(), DiagnosticKind::InvalidRule));
+            return Err(LSystemError::InvalidRule(rule.trim().to_string()));
+        };
+
+        self.rules
+            .push(ProductionRule::new(location, predecessor, Successor::Tokens(successor)));
 
         Ok(())
     }
 
-    pub fn axiom(&mut self, axiom: Vec<TokenId>) -> Result<(), LSystemError> {
-        self.validate_ids(axiom.as_slice())?;
+    /// Sets the axiom from text, e.g. `F[+A]`, see [`LSystemBuilder::parse_rule`].
+    pub fn parse_axiom(&mut self, axiom: &str) -> Result<(), LSystemError> {
+        let axiom = self.parse_tokens(Location::Axiom, axiom, axiom)?;
         self.axiom = Some(axiom);
 
         Ok(())
     }
 
-    pub fn finish(self) -> Result<LSystem, LSystemError> {
-        let axiom = self.axiom.ok_or(LSystemError::MissingAxiom)?;
+    fn parse_tokens(&mut self, location: Location, text: &str, source: &str) -> Result<Vec<TokenId>, LSystemError> {
+        let mut tokens = Vec::new();
+        let mut rest = text.trim_start();
+
+        while !rest.is_empty() {
+            // Prefer the longest name, and the first declared among equal names.
+            let mut longest: Option<(TokenId, usize)> = None;
+            for (id, token) in self.arena.enumerate() {
+                let len = token.name().len();
+                if len > 0 && rest.starts_with(token.name()) && longest.is_none_or(|(_, l)| len > l) {
+                    longest = Some((id, len));
+                }
+            }
+
+            let Some((id, len)) = longest else {
+                let unknown = rest.split_whitespace().next().unwrap_or(rest).to_string();
+                self.rejected.push(Diagnostic::new(
+                    location,
+                    source.trim(),
+                    DiagnosticKind::UnknownToken(unknown.clone()),
+                ));
+                return Err(LSystemError::UnknownToken(unknown));
+            };
+
+            tokens.push(id);
+            rest = &rest[len..];
+
+            let (arity, kind) = self.arena.get_token(&id).map_or((0, None), |token| (token.param(), ModuleKind::of(token)));
+
+            // After a token that holds no values, a parenthesis only opens a
+            // parameter list if it doesn't start the name of another token.
+            let starts_token = self.arena.iter_tokens().any(|token| !token.name().is_empty() && rest.starts_with(token.name()));
+            if let Some(list) = rest.strip_prefix('(').filter(|_| kind.is_some() || !starts_token) {
+                let Some((parameters, after)) = list.split_once(')') else {
+                    self.rejected.push(Diagnostic::new(location, source.trim(), DiagnosticKind::InvalidRule));
+                    return Err(LSystemError::InvalidRule(source.trim().to_string()));
+                };
+                let name = self.arena.get_token(&id).map(|token| token.name().to_string()).unwrap_or_default();
+
+                if kind.is_none() {
+                    self.rejected.push(Diagnostic::new(location, source.trim(), DiagnosticKind::UnkeptParameters(id)));
+                    return Err(LSystemError::UnkeptParameters(name));
+                }
+
+                let expected = arity as usize;
+                let found = match parameters.trim() {
+                    "" => 0,
+                    parameters => parameters.split(',').count(),
+                };
+                if found != expected {
+                    self.rejected.push(Diagnostic::new(
+                        location,
+                        source.trim(),
+                        DiagnosticKind::ArityMismatch { token: id, expected, found },
+                    ));
+                    return Err(LSystemError::ArityMismatch { name, expected, found });
+                }
+
+                rest = after;
+            }
+
+            rest = rest.trim_start();
+        }
+
+        Ok(tokens)
+    }
+
+    /// Analyses the grammar for likely mistakes: unreachable tokens, rules that
+    /// are never applied or are shadowed, tokens that always erase themselves
+    /// and duplicate token names.
+    pub fn lint(&self) -> Vec<LintWarning> {
+        lint::lint(self)
+    }
+
+    pub(crate) fn arena(&self) -> &Arena {
+        &self.arena
+    }
 
+    pub(crate) fn axiom_tokens(&self) -> Option<&[TokenId]> {
+        self.axiom.as_deref()
+    }
+
+    /// Production rules followed by decomposition rules, with their locations.
+    pub(crate) fn locations(&self) -> impl Iterator<Item = (Location, &ProductionRule)> {
+        self.rules
+            .iter()
+            .chain(self.decompositions.iter())
+            .map(|rule| (rule.location, rule))
+    }
+
+    pub(crate) fn rule_string(&self, location: Location) -> String {
+        match location {
+            Location::Token(_) | Location::BranchTokens => String::new(),
+            Location::Axiom => self.axiom
+                .as_deref()
+                .map(|axiom| render_tokens(&self.arena, axiom))
+                .unwrap_or_default(),
+            Location::Rule(_) | Location::Decomposition(_) => self.locations()
+                .find(|(other, _)| *other == location)
+                .map(|(_, rule)| build_rules_string(std::slice::from_ref(rule), &self.arena))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn finish(mut self) -> Result<LSystem, LSystemError> {
+        if let Some(&location) = self.unbalanced().first() {
+            return Err(LSystemError::UnbalancedBranches(self.rule_string(location)));
+        }
+
+        let axiom = self.axiom.take().ok_or(LSystemError::MissingAxiom)?;
+
+        Ok(self.build(axiom))
+    }
+
+    /// Like [`LSystemBuilder::finish`], but instead of stopping at the first
+    /// problem, reports every problem found since the builder was created:
+    /// invalid token names, invalid ids and unknown tokens rejected while
+    /// declaring tokens, branch tokens and rules, a missing axiom, duplicate
+    /// rules and unbalanced branches.
+    pub fn finish_with_diagnostics(mut self) -> Result<LSystem, Diagnostics> {
+        let mut diagnostics = self.rejected.clone();
+
+        if self.axiom.is_none() {
+            diagnostics.push(Diagnostic::new(Location::Axiom, "", DiagnosticKind::MissingAxiom));
+        }
+
+        for warning in self.lint() {
+            if let LintWarning::ShadowedRule { location, shadowed_by, rule } = warning {
+                diagnostics.push(Diagnostic::new(location, rule, DiagnosticKind::DuplicateRule(shadowed_by)));
+            }
+        }
+
+        for location in self.unbalanced() {
+            diagnostics.push(Diagnostic::new(location, self.rule_string(location), DiagnosticKind::UnbalancedBranches));
+        }
+
+        match self.axiom.take() {
+            Some(axiom) if diagnostics.is_empty() => Ok(self.build(axiom)),
+            _ => Err(Diagnostics::new(diagnostics)),
+        }
+    }
+
+    fn build(self, axiom: Vec<TokenId>) -> LSystem {
         // Construct a HashMap associating each variable with its corresponding transformation rule
         let mut rules_map = HashMap::new();
+        let mut production_fns = HashMap::new();
+        let mut rule_indices = HashMap::new();
+
+        for rule in self.rules {
+            if let Location::Rule(index) = rule.location {
+                rule_indices.insert(rule.predecessor, index);
+            }
 
-        for rule in self.rules.into_iter() {
-            rules_map.insert(rule.predecessor, rule.successor);
+            match rule.successor {
+                Successor::Tokens(successor) => {
+                    production_fns.remove(&rule.predecessor);
+                    rules_map.insert(rule.predecessor, successor);
+                }
+                Successor::Function(f) => {
+                    rules_map.remove(&rule.predecessor);
+                    production_fns.insert(rule.predecessor, f);
+                }
+            }
         }
 
         // We also add constant production rules of the form P => P.
         for (id, _token) in self.arena.enumerate() {
             // no rule associated to this token, so its a constant token
-            rules_map.entry(id).or_insert_with(|| vec![id]);
+            if !production_fns.contains_key(&id) {
+                rules_map.entry(id).or_insert_with(|| vec![id]);
+            }
         }
 
         // If we set our system up correctly, it should be that each token
         // contributes exactly one rule, so we check for that here.
-        assert_eq!(self.arena.len() as usize, rules_map.len());
+        assert_eq!(self.arena.len() as usize, rules_map.len() + production_fns.len());
+
+        // Decompositions only hold explicit rules; tokens without one are left untouched.
+        let mut decompositions = HashMap::new();
+        let mut decomposition_indices = HashMap::new();
+
+        for rule in self.decompositions {
+            if let (Location::Decomposition(index), Successor::Tokens(successor)) = (rule.location, rule.successor) {
+                decomposition_indices.insert(rule.predecessor, index);
+                decompositions.insert(rule.predecessor, successor);
+            }
+        }
+
+        let seed = self.seed.unwrap_or_else(rand::random);
+
+        let grammar = Grammar::new(self.arena, axiom, rules_map)
+            .with_production_fns(production_fns)
+            .with_rule_indices(rule_indices)
+            .with_decompositions(decompositions, decomposition_indices, self.decomposition_depth)
+            .with_branches(self.branches)
+            .with_turtle(self.turtle)
+            .with_seed(seed);
+
+        LSystem::from_grammar(Arc::new(grammar))
+    }
+}
+
+pub(crate) fn is_balanced(tokens: &[TokenId], open: TokenId, close: TokenId) -> bool {
+    let mut depth = 0usize;
 
-        Ok(LSystem::new(self.arena, axiom, rules_map))
+    for &id in tokens {
+        if id == open {
+            depth += 1;
+        } else if id == close {
+            match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            }
+        }
     }
+
+    depth == 0
 }
 
-fn render_tokens(arena: &Arena, tokens: &[TokenId]) -> String {
+pub(crate) fn render_tokens(arena: &Arena, tokens: &[TokenId]) -> String {
     let tokens = tokens.iter()
-        .flat_map(|id| arena.get_token(id))
-        .map(|token| token.name()).collect::<Vec<_>>();
+        .map(|id| match arena.get_token(id) {
+            Some(token) => token.name().to_string(),
+            None => format!("<{}>", id),
+        })
+        .collect::<Vec<_>>();
+
+    tokens.join("")
+}
+
+/// Like `render_tokens`, but handles of other arenas are rendered as ids
+/// rather than as whatever token of this arena shares their value.
+fn render_handles(arena: &Arena, handles: &[TokenHandle]) -> String {
+    let tokens = handles.iter()
+        .map(|handle| match arena.get_token(&handle.id()) {
+            Some(token) if arena.owns(handle) => token.name().to_string(),
+            _ => format!("<{}>", handle),
+        })
+        .collect::<Vec<_>>();
 
     tokens.join("")
 }
 
+fn render_handle_rule(arena: &Arena, predecessor: TokenHandle, successor: &[TokenHandle]) -> String {
+    format!("{} => {}", render_handles(arena, &[predecessor]), render_handles(arena, successor))
+}
+
 fn build_rules_string(rules: &[ProductionRule], arena: &Arena) -> String {
     let mut st = Vec::new();
 
     for rule in rules {
+        let successor = match &rule.successor {
+            Successor::Tokens(successor) => render_tokens(arena, successor),
+            Successor::Function(f) => format!("{:?}", f),
+        };
+
         st.push(format!(
             "{} => {}",
             render_tokens(arena, &[rule.predecessor]),
-            render_tokens(arena, &rule.successor),
+            successor,
         ));
     }
 
@@ -123,6 +628,13 @@ impl std::fmt::Debug for LSystemBuilder {
             .field("arena", &self.arena)
             .field("axiom", &self.axiom)
             .field("rules", &build_rules_string(&self.rules, &self.arena))
+            .field("decompositions", &build_rules_string(&self.decompositions, &self.arena))
+            .field("decomposition_depth", &self.decomposition_depth)
+            .field("turtle", &self.turtle)
+            .field("seed", &self.seed)
+            .field("branches", &self.branches)
+            .field("rejected", &self.rejected)
+            .field("digit_suffix_arity", &self.digit_suffix_arity)
             .finish()
     }
 }
@@ -168,6 +680,271 @@ mod tests {
             .production_rule(y, vec![q, q])
             .is_err());
 
+        // `x` has the same numeric value as `q`, but still belongs to `builder`.
+        assert_eq!(x.value(), q.value());
+        assert!(matches!(
+            some_other_builder.production_rule(q, vec![x]),
+            Err(LSystemError::ForeignTokenId(id)) if id == x
+        ));
+        assert!(matches!(
+            some_other_builder.axiom(vec![x]),
+            Err(LSystemError::ForeignTokenId(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_clone_rejects_later_tokens() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let a = builder.token("A")?;
+
+        let mut clone = builder.clone();
+        let x = builder.token("X")?;
+        let y = clone.token("Y")?;
+
+        // Both builders know `A`, but `X` and `Y` share a value and only
+        // belong to the builder that created them.
+        assert!(matches!(clone.axiom(vec![a, x]), Err(LSystemError::ForeignTokenId(id)) if id == x));
+        assert!(matches!(builder.axiom(vec![a, y]), Err(LSystemError::ForeignTokenId(id)) if id == y));
+
+        clone.axiom(vec![a, y])?;
+        assert_eq!(clone.finish()?.render(), "AY");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_module_arity() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let f2 = builder.token("F2")?;
+        assert!(!f2.has_param());
+
+        // Only query and communication modules hold values.
+        assert!(matches!(
+            builder.module("F", 2),
+            Err(LSystemError::ArityMismatch { expected: 0, found: 2, .. })
+        ));
+
+        builder.digit_suffix_arity(true);
+        assert!(matches!(
+            builder.token("Layer10"),
+            Err(LSystemError::ArityMismatch { expected: 0, found: 10, .. })
+        ));
+        let light = builder.token("?E3")?;
+        assert!(light.has_param());
+        assert_eq!(builder.arena.get_token(&light.id()).map(Token::param), Some(3));
+
+        // Query modules are always filled with a position.
+        assert!(matches!(
+            builder.token("?P"),
+            Err(LSystemError::ArityMismatch { expected: 2, found: 0, .. })
+        ));
+        assert!(matches!(
+            builder.module("?P", 3),
+            Err(LSystemError::ArityMismatch { expected: 2, found: 3, .. })
+        ));
+        let query = builder.module("?P", 2)?;
+
+        // Modules are used through handles like any other token.
+        let a = builder.token("A")?;
+        builder.production_rule(a, vec![a, light, query])?;
+        builder.axiom(vec![a])?;
+        let mut system = builder.clone().finish()?;
+        system.step();
+        assert_eq!(system.render(), "A?E3?P");
+        system.set_state(vec![query, light])?;
+        assert_eq!(system.render(), "?P?E3");
+
+        // Placeholders of modules have to match their arity, and no
+        // parameters can be written for other tokens.
+        builder.parse_rule("A => A?P(x, y)?E3(a, b, c)")?;
+        builder.parse_rule("A => A?P?E3")?;
+        assert!(matches!(
+            builder.parse_rule("A => A?P(x)"),
+            Err(LSystemError::ArityMismatch { expected: 2, found: 1, .. })
+        ));
+        assert!(matches!(builder.parse_rule("F2(x, y) => F2"), Err(LSystemError::UnkeptParameters(name)) if name == "F2"));
+        assert!(matches!(builder.parse_axiom("A(x)"), Err(LSystemError::UnkeptParameters(name)) if name == "A"));
+
+        let diagnostics = builder.finish_with_diagnostics().unwrap_err();
+        let kinds = diagnostics.iter().map(|diagnostic| diagnostic.kind.clone()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            DiagnosticKind::InvalidArity { expected: 0, found: 2 },
+            DiagnosticKind::InvalidArity { expected: 0, found: 10 },
+            DiagnosticKind::InvalidArity { expected: 2, found: 0 },
+            DiagnosticKind::InvalidArity { expected: 2, found: 3 },
+            DiagnosticKind::UnkeptParameters(a.id()),
+            DiagnosticKind::DuplicateRule(Location::Rule(1)),
+            DiagnosticKind::DuplicateRule(Location::Rule(2)),
+            DiagnosticKind::ArityMismatch { token: query.id(), expected: 2, found: 1 },
+            DiagnosticKind::UnkeptParameters(f2.id()),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_parse_rules() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let ab = builder.token("AB")?;
+        let f = builder.token("F")?;
+
+        builder.parse_axiom("A AB")?;
+        builder.parse_rule("A => F ABA")?;
+        builder.parse_rule("AB=>")?;
+
+        assert_eq!(builder.axiom, Some(vec![a.id(), ab.id()]));
+        assert_eq!(build_rules_string(&builder.rules, &builder.arena), "A => FABA,AB => ");
+        assert_eq!(builder.rules[0].successor.tokens(), Some(&[f.id(), ab.id(), a.id()][..]));
+
+        assert!(matches!(builder.parse_rule("A => FX"), Err(LSystemError::UnknownToken(t)) if t == "X"));
+        assert!(matches!(builder.parse_rule("A F => F"), Err(LSystemError::InvalidRule(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_finish_with_diagnostics() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let open = builder.token("[")?;
+        let close = builder.token("]")?;
+        builder.branch_tokens(open, close)?;
+
+        let invalid = TokenId::new(9, false);
+        assert!(builder.production_rule(a, vec![invalid.into(), a]).is_err());
+        assert!(builder.parse_rule("A => [Q").is_err());
+        builder.parse_rule("A => [A")?;
+        builder.parse_rule("A => [A]")?;
+
+        let misflagged = TokenHandle::new(TokenId::new(a.value(), true), a.arena());
+        assert!(builder.decomposition_rule(a, vec![misflagged]).is_err());
+
+        let diagnostics = builder.finish_with_diagnostics().unwrap_err();
+
+        assert_eq!(
+            diagnostics.iter().cloned().collect::<Vec<_>>(),
+            vec![
+                Diagnostic::new(Location::Axiom, "", DiagnosticKind::MissingAxiom),
+                Diagnostic::new(Location::Rule(0), "A => <9>A", DiagnosticKind::UnbrandedToken(invalid)),
+                Diagnostic::new(Location::Rule(1), "A => [Q", DiagnosticKind::UnknownToken("Q".to_string())),
+                Diagnostic::new(Location::Rule(2), "A => [A", DiagnosticKind::DuplicateRule(Location::Rule(3))),
+                Diagnostic::new(Location::Rule(2), "A => [A", DiagnosticKind::UnbalancedBranches),
+                Diagnostic::new(Location::Decomposition(0), "A => <0p>", DiagnosticKind::MisflaggedTokenId(misflagged.id())),
+            ]
+        );
+        assert_eq!(diagnostics.to_string().lines().count(), 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_diagnostics_for_rejected_branch_tokens() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        let mut other = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let open = builder.token("[")?;
+        let _close = builder.token("]")?;
+        let foreign = other.token("]")?;
+
+        builder.axiom(vec![a])?;
+        assert!(builder.branch_tokens(open, foreign).is_err());
+        builder.parse_rule("A => A[")?;
+
+        let diagnostics = builder.finish_with_diagnostics().unwrap_err();
+
+        assert_eq!(
+            diagnostics.iter().cloned().collect::<Vec<_>>(),
+            vec![Diagnostic::new(Location::BranchTokens, "[<0>", DiagnosticKind::ForeignTokenId(foreign.id()))]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_diagnostics_for_rejected_tokens() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        assert!(builder.token("bad name").is_err());
+        assert!(builder.module("bad module", 2).is_err());
+
+        builder.axiom(vec![a])?;
+
+        let diagnostics = builder.finish_with_diagnostics().unwrap_err();
+
+        assert_eq!(
+            diagnostics.iter().cloned().collect::<Vec<_>>(),
+            vec![
+                Diagnostic::new(Location::Token(1), "bad name", DiagnosticKind::InvalidToken),
+                Diagnostic::new(Location::Token(2), "bad module", DiagnosticKind::InvalidToken),
+            ]
+        );
+        assert_eq!(diagnostics.to_string().lines().next(), Some("token #1 `bad name`: invalid token name"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_unbalanced_branches() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let f = builder.token("F")?;
+        let plus = builder.token("+")?;
+        let open = builder.token("[")?;
+        let close = builder.token("]")?;
+
+        builder.axiom(vec![a])?;
+        builder.production_rule(a, vec![f, open, plus, a, close])?;
+
+        let mut unbalanced = builder.clone();
+        unbalanced.production_rule(a, vec![f, open, plus, a])?;
+
+        // Without declared branch tokens anything goes.
+        assert!(unbalanced.clone().finish().is_ok());
+
+        unbalanced.branch_tokens(open, close)?;
+        match unbalanced.finish() {
+            Err(LSystemError::UnbalancedBranches(rule)) => assert_eq!(rule, "A => F[+A"),
+            other => panic!("expected unbalanced branches, got {:?}", other),
+        }
+
+        builder.branch_tokens(open, close)?;
+        assert!(builder.clone().finish().is_ok());
+
+        // Rules may only rewrite a branch token into itself.
+        let mut rewritten = builder.clone();
+        rewritten.production_rule(close, vec![close])?;
+        assert!(rewritten.clone().finish().is_ok());
+        rewritten.production_rule(open, vec![f])?;
+        match rewritten.clone().finish() {
+            Err(LSystemError::UnbalancedBranches(rule)) => assert_eq!(rule, "[ => F"),
+            other => panic!("expected unbalanced branches, got {:?}", other),
+        }
+        rewritten.decomposition_rule(close, vec![close, close])?;
+        let diagnostics = rewritten.finish_with_diagnostics().unwrap_err();
+        assert_eq!(
+            diagnostics.iter().map(|diagnostic| diagnostic.location).collect::<Vec<_>>(),
+            vec![Location::Rule(2), Location::Decomposition(0)]
+        );
+
+        let mut function = builder.clone();
+        function.production_fn(close, |context| vec![context.token()])?;
+        assert!(matches!(function.finish(), Err(LSystemError::UnbalancedBranches(_))));
+
+        builder.axiom(vec![close, a, open])?;
+        match builder.finish() {
+            Err(LSystemError::UnbalancedBranches(axiom)) => assert_eq!(axiom, "]A["),
+            other => panic!("expected unbalanced branches, got {:?}", other),
+        }
+
         Ok(())
     }
 }
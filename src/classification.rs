@@ -0,0 +1,55 @@
+use std::fmt::Display;
+
+/// The family of L-systems a system belongs to, see [`LSystem::classify`](crate::LSystem::classify).
+///
+/// What production functions return can't be inspected, so properties that
+/// depend on it are `None` for systems using them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Classification {
+    /// Every token has exactly one successor. Known to be true when every
+    /// token is rewritten by a fixed rule.
+    pub deterministic: Option<bool>,
+    /// No token is ever erased. Known to be false as soon as a fixed rule
+    /// erases its token.
+    pub propagating: Option<bool>,
+    /// Successors don't depend on the neighbours of a token. Fixed rules
+    /// can't express context-sensitive rewriting, only production functions
+    /// may read the neighbours, so this is never known to be false.
+    pub context_free: Option<bool>,
+    /// Some query or communication module holds parameter values.
+    pub parametric: bool,
+    /// The axiom or a rule opens a branch.
+    pub bracketed: bool,
+}
+
+impl Classification {
+    /// The conventional name of the family, such as `PD0L` or `0L`. Only
+    /// properties known to hold are named, so a system whose context
+    /// sensitivity is unknown is a plain `L` system.
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+
+        if self.propagating == Some(true) {
+            name.push('P');
+        }
+        if self.deterministic == Some(true) {
+            name.push('D');
+        }
+        name.push_str(if self.context_free == Some(true) { "0L" } else { "L" });
+
+        name
+    }
+}
+
+impl Display for Classification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())?;
+
+        match (self.parametric, self.bracketed) {
+            (true, true) => write!(f, " (parametric, bracketed)"),
+            (true, false) => write!(f, " (parametric)"),
+            (false, true) => write!(f, " (bracketed)"),
+            (false, false) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt::Display;
+
+use crate::builder::Location;
+use crate::token::TokenId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    InvalidToken,
+    InvalidTokenId(TokenId),
+    ForeignTokenId(TokenId),
+    UnbrandedToken(TokenId),
+    MisflaggedTokenId(TokenId),
+    UnknownToken(String),
+    /// A different number of parameters is written for the module than it is declared with.
+    ArityMismatch { token: TokenId, expected: usize, found: usize },
+    /// The token is declared with a different number of parameters than it is filled with.
+    InvalidArity { expected: usize, found: usize },
+    /// Parameters are written for a token that holds no values.
+    UnkeptParameters(TokenId),
+    InvalidRule,
+    MissingAxiom,
+    /// The rule is replaced by the rule at the given location.
+    DuplicateRule(Location),
+    UnbalancedBranches,
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::InvalidToken => write!(f, "invalid token name"),
+            DiagnosticKind::InvalidTokenId(id) => write!(f, "invalid token ID `{}`", id),
+            DiagnosticKind::ForeignTokenId(id) => write!(f, "token ID `{}` belongs to another arena", id),
+            DiagnosticKind::UnbrandedToken(id) => write!(f, "token ID `{}` belongs to no arena", id),
+            DiagnosticKind::MisflaggedTokenId(id) => {
+                write!(f, "token ID `{}` doesn't match whether its token takes parameters", id)
+            }
+            DiagnosticKind::UnknownToken(name) => write!(f, "unknown token `{}`", name),
+            DiagnosticKind::ArityMismatch { token, expected, found } => {
+                write!(f, "token `{}` takes {} parameters, got {}", token, expected, found)
+            }
+            DiagnosticKind::InvalidArity { expected, found } => {
+                write!(f, "declared with {} parameters, but takes {}", found, expected)
+            }
+            DiagnosticKind::UnkeptParameters(token) => {
+                write!(f, "parameters of token `{}` aren't kept, only query and communication modules hold values", token)
+            }
+            DiagnosticKind::InvalidRule => write!(f, "expected a single predecessor followed by `=>`"),
+            DiagnosticKind::MissingAxiom => write!(f, "axiom has not been defined"),
+            DiagnosticKind::DuplicateRule(by) => write!(f, "duplicate rule, replaced by {}", by),
+            DiagnosticKind::UnbalancedBranches => write!(f, "unbalanced branches"),
+        }
+    }
+}
+
+/// A single problem found in a grammar, with where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub location: Location,
+    /// The rule or axiom as written, or rendered from its tokens.
+    pub text: String,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub(crate) fn new<S: Into<String>>(location: Location, text: S, kind: DiagnosticKind) -> Self {
+        Self {
+            location,
+            text: text.into(),
+            kind,
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.text.is_empty() {
+            write!(f, "{}: {}", self.location, self.kind)
+        } else {
+            write!(f, "{} `{}`: {}", self.location, self.text, self.kind)
+        }
+    }
+}
+
+/// Every problem found by [`LSystemBuilder::finish_with_diagnostics`](crate::LSystemBuilder::finish_with_diagnostics).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub(crate) fn new(mut diagnostics: Vec<Diagnostic>) -> Self {
+        diagnostics.sort_by_key(|diagnostic| diagnostic.location);
+        Self(diagnostics)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -0,0 +1,50 @@
+use crate::token::Token;
+use crate::turtle::TurtleState;
+
+/// Name of the query module, whose parameters are filled with the turtle
+/// position `(x, y)` after each step. It must be declared with an arity of
+/// [`QUERY_ARITY`].
+pub const QUERY_MODULE: &str = "?P";
+
+pub const QUERY_ARITY: u8 = 2;
+
+/// Prefix of communication modules, whose parameters are supplied by an
+/// [`Environment`] after each step. The environment must respond with as many
+/// values as the module's arity.
+pub const COMMUNICATION_PREFIX: &str = "?E";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    Query,
+    Communication,
+}
+
+impl ModuleKind {
+    pub fn of(token: &Token) -> Option<Self> {
+        if token.name() == QUERY_MODULE {
+            Some(ModuleKind::Query)
+        } else if token.name().starts_with(COMMUNICATION_PREFIX) {
+            Some(ModuleKind::Communication)
+        } else {
+            None
+        }
+    }
+}
+
+/// A communication module found in the state, passed to the [`Environment`].
+#[derive(Debug, Clone, Copy)]
+pub struct Query<'a> {
+    pub token: &'a Token,
+    /// Position of the module in the state.
+    pub index: usize,
+    /// Turtle state at the module.
+    pub turtle: TurtleState,
+    /// Number of steps taken so far.
+    pub steps: usize,
+}
+
+/// The environment of an open L-system. It is consulted between steps and
+/// supplies the parameter values of every communication module in the state.
+pub trait Environment {
+    fn respond(&mut self, query: &Query) -> Vec<f64>;
+}
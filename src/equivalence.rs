@@ -0,0 +1,299 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+use crate::errors::LSystemError;
+use crate::system::LSystem;
+use crate::token::TokenId;
+
+/// Whether two deterministic systems generate the same sequence of words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Equivalence {
+    /// Both systems generate the same word in every generation.
+    Equivalent,
+    /// The words of `generation` differ, and agree in every earlier one.
+    Differ { generation: usize },
+}
+
+/// Decides whether `a` and `b` generate the same sequence of words, starting
+/// from their axioms, or finds the first generation where the words differ.
+/// Words are compared by token names, so the systems may declare their tokens
+/// in a different order.
+///
+/// When generation `n` agrees, generation `n + 1` agrees if the rules of `b`
+/// rewrite the word of `a` the same way as those of `a` do. Both rewritings
+/// are compared token by token, tracking how far one runs ahead of the other.
+/// Each word of `a` is summarized by where it takes that comparison, and the
+/// summaries eventually repeat, which settles all later generations at once.
+/// When one rewriting runs too far ahead the comparison restarts with twice
+/// the bound: equivalent D0L systems stay within a bound (Culik and Fris), and
+/// a difference is found once the bound covers the generations before it.
+///
+/// Returns [`LSystemError::AmbiguousToken`] if several tokens of `b` are
+/// named like a token reachable in `a`.
+pub fn equivalence(a: &LSystem, b: &LSystem) -> Result<Equivalence, LSystemError> {
+    if !a.grammar().is_deterministic() || !b.grammar().is_deterministic() {
+        return Err(LSystemError::NotDeterministic);
+    }
+
+    let mut names = Names::default();
+    let names_a = names.of(a);
+    let names_b = names.of(b);
+    let word = |names: &[u16], tokens: &[TokenId]| {
+        tokens.iter().map(|id| names[id.value() as usize]).collect::<Vec<_>>()
+    };
+
+    if word(&names_a, a.axiom()) != word(&names_b, b.axiom()) {
+        return Ok(Equivalence::Differ { generation: 0 });
+    }
+
+    let mut by_name = HashMap::new();
+    for (id, _) in b.arena().enumerate() {
+        by_name.entry(names_b[id.value() as usize]).or_insert_with(Vec::new).push(id);
+    }
+
+    // Number the tokens reachable in `a`, in the order they are found.
+    let mut indices = HashMap::new();
+    let mut reachable = Vec::new();
+    let axiom = a.axiom()
+        .iter()
+        .map(|&id| index_of(id, &mut indices, &mut reachable))
+        .collect::<Vec<_>>();
+
+    let mut letters = Vec::new();
+    while let Some(&id) = reachable.get(letters.len()) {
+        let successor = a.grammar().successor_of(id).unwrap_or_default();
+        let name = names_a[id.value() as usize];
+        let right = match by_name.get(&name).map(Vec::as_slice) {
+            // The name never occurs in `b`, so the words differ before `id`
+            // is ever rewritten.
+            None => None,
+            Some(&[id_b]) => Some(word(&names_b, &b.grammar().successor_of(id_b).unwrap_or_default())),
+            Some(_) => {
+                let token = a.arena().get_token(&id).map(|token| token.name().to_string());
+                return Err(LSystemError::AmbiguousToken(token.unwrap_or_default()));
+            }
+        };
+
+        letters.push(Letter {
+            successor: successor.iter().map(|&id| index_of(id, &mut indices, &mut reachable)).collect(),
+            left: word(&names_a, &successor),
+            right,
+        });
+    }
+
+    let mut bound = 1;
+    loop {
+        if let Some(equivalence) = Comparison::new(&letters, bound).settle(&letters, &axiom) {
+            return Ok(equivalence);
+        }
+        bound *= 2;
+    }
+}
+
+fn index_of(id: TokenId, indices: &mut HashMap<TokenId, usize>, reachable: &mut Vec<TokenId>) -> usize {
+    *indices.entry(id).or_insert_with(|| {
+        reachable.push(id);
+        reachable.len() - 1
+    })
+}
+
+/// A token reachable in the first system, with the names its successor has
+/// in either system.
+struct Letter {
+    /// Indices of the letters of the successor in the first system.
+    successor: Vec<usize>,
+    left: Vec<u16>,
+    /// `None` if the second system has no token of that name.
+    right: Option<Vec<u16>>,
+}
+
+/// How the rewritings of a word by both systems compare so far.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Lead {
+    Even,
+    /// The first rewriting is ahead by these names.
+    Left(Vec<u16>),
+    /// The second rewriting is ahead by these names.
+    Right(Vec<u16>),
+    Mismatch,
+    /// One rewriting got further ahead than the bound.
+    Overflow,
+}
+
+impl Lead {
+    fn advance(&self, letter: &Letter, bound: usize) -> Lead {
+        let Some(right) = &letter.right else {
+            return Lead::Mismatch;
+        };
+
+        let (left, right) = match self {
+            Lead::Even => (letter.left.clone(), right.clone()),
+            Lead::Left(ahead) => ([ahead.as_slice(), &letter.left].concat(), right.clone()),
+            Lead::Right(ahead) => (letter.left.clone(), [ahead.as_slice(), right].concat()),
+            Lead::Mismatch | Lead::Overflow => return self.clone(),
+        };
+
+        let common = left.len().min(right.len());
+        if left[..common] != right[..common] {
+            return Lead::Mismatch;
+        }
+        if left.len().abs_diff(right.len()) > bound {
+            return Lead::Overflow;
+        }
+
+        match left.len().cmp(&right.len()) {
+            Ordering::Less => Lead::Right(right[common..].to_vec()),
+            Ordering::Equal => Lead::Even,
+            Ordering::Greater => Lead::Left(left[common..].to_vec()),
+        }
+    }
+}
+
+/// Every way the rewritings of a word can compare within a bound, as a finite
+/// automaton reading the word. State `0` is [`Lead::Even`].
+struct Comparison {
+    leads: Vec<Lead>,
+    /// The state reached from each state by each letter.
+    transitions: Vec<Vec<usize>>,
+}
+
+impl Comparison {
+    fn new(letters: &[Letter], bound: usize) -> Self {
+        let mut leads = vec![Lead::Even];
+        let mut states = HashMap::from([(Lead::Even, 0)]);
+        let mut transitions = Vec::new();
+
+        while let Some(lead) = leads.get(transitions.len()) {
+            let next = letters.iter().map(|letter| lead.advance(letter, bound)).collect::<Vec<_>>();
+            let row = next.into_iter()
+                .map(|lead| match states.entry(lead) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        leads.push(entry.key().clone());
+                        *entry.insert(leads.len() - 1)
+                    }
+                })
+                .collect();
+            transitions.push(row);
+        }
+
+        Self { leads, transitions }
+    }
+
+    /// Compares the rewritings of every generation of the first system, or
+    /// returns `None` if one overflows the bound before a difference is found.
+    fn settle(&self, letters: &[Letter], axiom: &[usize]) -> Option<Equivalence> {
+        // The state each state moves to by reading the `n`th successor of
+        // every letter, starting with the letter itself.
+        let mut moves = (0..letters.len())
+            .map(|letter| self.transitions.iter().map(|row| row[letter]).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+
+        for generation in 0.. {
+            let state = axiom.iter().fold(0, |state, &letter| moves[letter][state]);
+            match self.leads[state] {
+                Lead::Even => {}
+                Lead::Overflow => return None,
+                _ => return Some(Equivalence::Differ { generation: generation + 1 }),
+            }
+
+            // Later generations move like earlier ones, which agreed.
+            if !seen.insert(moves.clone()) {
+                break;
+            }
+
+            moves = letters.iter()
+                .map(|letter| {
+                    (0..self.leads.len())
+                        .map(|state| letter.successor.iter().fold(state, |state, &next| moves[next][state]))
+                        .collect()
+                })
+                .collect();
+        }
+
+        Some(Equivalence::Equivalent)
+    }
+}
+
+/// Numbers token names so that words can be compared across arenas.
+#[derive(Default)]
+struct Names {
+    indices: HashMap<String, u16>,
+}
+
+impl Names {
+    fn of(&mut self, system: &LSystem) -> Vec<u16> {
+        system.arena()
+            .iter_tokens()
+            .map(|token| {
+                let next = self.indices.len() as u16;
+                *self.indices.entry(token.name().to_string()).or_insert(next)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LSystemBuilder;
+
+    fn system(tokens: &[&str], axiom: &str, rules: &[&str]) -> Result<LSystem, LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        for token in tokens {
+            builder.token(*token)?;
+        }
+        builder.parse_axiom(axiom)?;
+        for rule in rules {
+            builder.parse_rule(rule)?;
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn equivalence_of_reordered_tokens() -> Result<(), LSystemError> {
+        let a = system(&["A", "B"], "A", &["A => AB", "B => A"])?;
+        let b = system(&["B", "A"], "A", &["B => A", "A => AB"])?;
+
+        assert_eq!(equivalence(&a, &b)?, Equivalence::Equivalent);
+
+        Ok(())
+    }
+
+    #[test]
+    fn equivalence_finds_first_difference() -> Result<(), LSystemError> {
+        let a = system(&["A", "B"], "A", &["A => AB", "B => A"])?;
+        let b = system(&["A", "B"], "A", &["A => AB", "B => B"])?;
+
+        assert_eq!(equivalence(&a, &b)?, Equivalence::Differ { generation: 2 });
+
+        let c = system(&["A", "B"], "B", &["A => AB", "B => A"])?;
+        assert_eq!(equivalence(&a, &c)?, Equivalence::Differ { generation: 0 });
+
+        // Only `D` is rewritten differently, and it shows up in generation 3.
+        let a = system(&["A", "B", "C", "D"], "A", &["A => B", "B => C", "C => D", "D => D"])?;
+        let b = system(&["A", "B", "C", "D"], "A", &["A => B", "B => C", "C => D", "D => DD"])?;
+        assert_eq!(equivalence(&a, &b)?, Equivalence::Differ { generation: 4 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn equivalence_with_different_rules() -> Result<(), LSystemError> {
+        // Both generate (ab)^(2^n), but rewrite `a` and `b` differently.
+        let a = system(&["a", "b"], "ab", &["a => aba", "b => b"])?;
+        let b = system(&["a", "b"], "ab", &["a => a", "b => bab"])?;
+
+        assert_eq!(equivalence(&a, &b)?, Equivalence::Equivalent);
+
+        // Both are stuck on `ab`.
+        let a = system(&["a", "b"], "ab", &["a => ab", "b => "])?;
+        let b = system(&["a", "b"], "ab", &["a => ", "b => ab"])?;
+
+        assert_eq!(equivalence(&a, &b)?, Equivalence::Equivalent);
+
+        Ok(())
+    }
+}
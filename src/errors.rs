@@ -11,8 +11,32 @@ pub enum LSystemError {
     InvalidToken(String),
     #[error("attempted to construct invalid token ID `{0}` value must be <= 127")]
     InvalidTokenId(TokenId),
+    #[error("token ID `{0}` belongs to another arena")]
+    ForeignTokenId(TokenId),
+    #[error("token ID `{0}` belongs to no arena")]
+    UnbrandedToken(TokenId),
+    #[error("token ID `{0}` doesn't match whether its token takes parameters")]
+    MisflaggedTokenId(TokenId),
+    #[error("token `{name}` takes {expected} parameters, got {found}")]
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("parameters of `{0}` aren't kept, only query and communication modules hold values")]
+    UnkeptParameters(String),
+    #[error("several tokens are named `{0}`")]
+    AmbiguousToken(String),
     #[error("invalid rule `{0}`")]
     InvalidRule(String),
+    #[error("unbalanced branches in `{0}`")]
+    UnbalancedBranches(String),
+    #[error("the bracket at index {0} of the state has no match")]
+    UnmatchedBracket(usize),
+    #[error("the system is not deterministic and context-free")]
+    NotDeterministic,
+    #[error("the generation has too many tokens to count")]
+    TooManyTokens,
     #[error("axiom has not been defined")]
     MissingAxiom,
     #[error("io error")]
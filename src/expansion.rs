@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::Arena;
+use crate::errors::LSystemError;
+use crate::render::{self, RenderOptions};
+use crate::system::LSystem;
+use crate::token::TokenId;
+
+/// A shared subsequence: a token at depth 0, or the concatenation of the
+/// subsequences of its successor one level down.
+#[derive(Debug, Clone)]
+enum Node {
+    Token(TokenId),
+    Concat { children: Vec<usize>, len: u128 },
+}
+
+impl Node {
+    fn len(&self) -> u128 {
+        match self {
+            Node::Token(_) => 1,
+            Node::Concat { len, .. } => *len,
+        }
+    }
+}
+
+/// A generation of a deterministic system stored as a DAG of hash-consed
+/// subsequences, see [`LSystem::expand`]. Each token expanded `d` steps is
+/// stored once however often it occurs, so the size of the expansion grows
+/// with the number of generations rather than the number of tokens.
+#[derive(Debug, Clone)]
+pub struct Expansion {
+    arena: Arena,
+    nodes: Vec<Node>,
+    roots: Vec<usize>,
+    len: u128,
+}
+
+impl Expansion {
+    pub(crate) fn new(system: &LSystem, generations: usize) -> Result<Self, LSystemError> {
+        let successors = system.arena()
+            .enumerate()
+            .map(|(id, _)| system.grammar().successor_of(id).map(|successor| (id, successor)))
+            .collect::<Option<HashMap<_, _>>>()
+            .ok_or(LSystemError::NotDeterministic)?;
+
+        let mut expansion = Self {
+            arena: system.arena().clone(),
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            len: 0,
+        };
+
+        // Node of every token expanded `depth` steps, one level at a time.
+        let mut level = successors.keys()
+            .map(|id| (*id, expansion.push(Node::Token(*id))))
+            .collect::<HashMap<_, _>>();
+        let mut interned = HashMap::new();
+
+        for _ in 0..generations {
+            let mut next = HashMap::with_capacity(level.len());
+
+            for (id, successor) in successors.iter() {
+                let children = successor.iter().map(|id| level[id]).collect::<Vec<_>>();
+
+                let node = match children.as_slice() {
+                    // A single child is the same subsequence, e.g. for constants.
+                    [child] => *child,
+                    _ => match interned.get(&children) {
+                        Some(node) => *node,
+                        None => {
+                            let len = expansion.total_len(&children)?;
+                            let node = expansion.push(Node::Concat { children: children.clone(), len });
+                            interned.insert(children, node);
+                            node
+                        }
+                    },
+                };
+
+                next.insert(*id, node);
+            }
+
+            level = next;
+        }
+
+        expansion.roots = system.get_state().iter().map(|id| level[id]).collect();
+        expansion.len = expansion.total_len(&expansion.roots)?;
+
+        Ok(expansion)
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn total_len(&self, children: &[usize]) -> Result<u128, LSystemError> {
+        children.iter().try_fold(0u128, |len, child| {
+            len.checked_add(self.nodes[*child].len()).ok_or(LSystemError::TooManyTokens)
+        })
+    }
+
+    /// The number of tokens in the generation.
+    pub fn len(&self) -> u128 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of distinct subsequences stored.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    /// Returns the token at `index`, walking down from the root in time
+    /// proportional to the number of generations.
+    pub fn token_at(&self, mut index: u128) -> Option<TokenId> {
+        if index >= self.len {
+            return None;
+        }
+
+        let mut children = self.roots.as_slice();
+
+        loop {
+            for child in children {
+                let len = self.nodes[*child].len();
+                if index < len {
+                    match &self.nodes[*child] {
+                        Node::Token(id) => return Some(*id),
+                        Node::Concat { children: next, .. } => children = next,
+                    }
+                    break;
+                }
+                index -= len;
+            }
+        }
+    }
+
+    /// Iterates over the tokens of the generation in order.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            expansion: self,
+            stack: vec![(&self.roots, 0)],
+        }
+    }
+
+    /// Writes the names of the tokens of the generation to `writer`, without
+    /// materializing it. Expanded generations carry no parameter values.
+    pub fn render_to<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> Result<(), LSystemError> {
+        render::write_tokens(writer, &self.arena, self.iter(), |_| None, options)
+    }
+}
+
+impl<'a> IntoIterator for &'a Expansion {
+    type Item = TokenId;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the tokens of an [`Expansion`].
+pub struct Iter<'a> {
+    expansion: &'a Expansion,
+    stack: Vec<(&'a [usize], usize)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = TokenId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (children, position) = self.stack.last_mut()?;
+
+            let Some(child) = children.get(*position) else {
+                self.stack.pop();
+                continue;
+            };
+            *position += 1;
+
+            match &self.expansion.nodes[*child] {
+                Node::Token(id) => return Some(*id),
+                Node::Concat { children, .. } => self.stack.push((children, 0)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, LSystemError, RenderOptions};
+
+    #[test]
+    fn expansion_matches_stepping() -> Result<(), LSystemError> {
+        let mut system = fixtures::binary_tree()?;
+        let expansion = system.expand(6)?;
+        system.step_by(6);
+
+        assert_eq!(expansion.len(), system.get_state().len() as u128);
+        assert!(expansion.iter().eq(system.get_state().iter().copied()));
+        assert_eq!(expansion.token_at(100), Some(system.get_state()[100]));
+        assert_eq!(expansion.token_at(expansion.len()), None);
+
+        let mut rendered = Vec::new();
+        expansion.render_to(&mut rendered, &RenderOptions::new())?;
+        assert_eq!(String::from_utf8(rendered).unwrap(), system.render());
+
+        Ok(())
+    }
+
+    #[test]
+    fn expansion_of_algae_is_compact() -> Result<(), LSystemError> {
+        let system = fixtures::algae()?;
+        let expansion = system.expand(100)?;
+
+        // F(102) tokens, in one node per generation besides the two tokens.
+        assert_eq!(expansion.len(), 927_372_692_193_078_999_176);
+        assert_eq!(expansion.node_count(), 102);
+
+        // Even generations end in A, odd ones in B.
+        let name = |index| expansion.arena().get_token(&expansion.token_at(index).unwrap()).unwrap().name();
+        assert_eq!(name(expansion.len() - 1), "A");
+        assert_eq!(name(expansion.len() - 2), "B");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::Write;
+
+use crate::errors::LSystemError;
+use crate::system::LSystem;
+
+/// Writes the derivation tree of the first `generations` generations of
+/// `system`, starting from its axiom, as a Graphviz DOT digraph. Nodes are
+/// labelled with token names and edges with the rule that was applied,
+/// followed by one line for each decomposition rule applied after it.
+///
+/// The system itself is left untouched; the derivation is replayed on a copy.
+pub fn write_derivation_dot<W: Write>(
+    system: &LSystem,
+    generations: usize,
+    writer: &mut W,
+) -> Result<(), LSystemError> {
+    let mut system = system.clone();
+    system.reset();
+    system.track_provenance(true);
+
+    let mut states = vec![system.get_state().to_vec()];
+    for _ in 0..generations {
+        system.try_step()?;
+        states.push(system.get_state().to_vec());
+    }
+
+    let provenance = system.provenance().expect("provenance tracking was enabled above");
+
+    writeln!(writer, "digraph derivation {{")?;
+    writeln!(writer, "    node [shape=plaintext];")?;
+
+    for (generation, state) in states.iter().enumerate() {
+        write!(writer, "    {{ rank=same;")?;
+        for index in 0..state.len() {
+            write!(writer, " g{}_{};", generation, index)?;
+        }
+        writeln!(writer, " }}")?;
+
+        for (index, id) in state.iter().enumerate() {
+            let name = system.arena().get_token(id).map(|token| token.name()).unwrap_or_default();
+            writeln!(writer, "    g{}_{} [label=\"{}\"];", generation, index, escape(name))?;
+        }
+    }
+
+    let grammar = system.grammar();
+    for (generation, parents) in states.iter().enumerate().skip(1) {
+        for index in 0..parents.len() {
+            let Some(parent) = provenance.parent(generation, index) else {
+                continue;
+            };
+            // Constants are kept by their implicit identity rule.
+            let rule = match provenance.rule(generation, index).flatten() {
+                Some(rule) => grammar.rule_string_at(rule).unwrap_or_default(),
+                None => grammar.rule_string(states[generation - 1][parent]),
+            };
+
+            let mut label = escape(&rule);
+            for &decomposition in provenance.decompositions(generation, index).unwrap_or_default() {
+                let decomposition = grammar.decomposition_string_at(decomposition).unwrap_or_default();
+                label.push_str(&format!("\\ndecomposed by {}", escape(&decomposition)));
+            }
+
+            writeln!(
+                writer,
+                "    g{}_{} -> g{}_{} [label=\"{}\"];",
+                generation - 1,
+                parent,
+                generation,
+                index,
+                label,
+            )?;
+        }
+    }
+
+    writeln!(writer, "}}")?;
+
+    Ok(())
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn derivation_dot_algae() -> Result<(), LSystemError> {
+        let mut system = fixtures::algae()?;
+        system.step_by(5);
+
+        let mut dot = Vec::new();
+        export::write_derivation_dot(&system, 2, &mut dot)?;
+
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            r#"digraph derivation {
+    node [shape=plaintext];
+    { rank=same; g0_0; }
+    g0_0 [label="A"];
+    { rank=same; g1_0; g1_1; }
+    g1_0 [label="A"];
+    g1_1 [label="B"];
+    { rank=same; g2_0; g2_1; g2_2; }
+    g2_0 [label="A"];
+    g2_1 [label="B"];
+    g2_2 [label="A"];
+    g0_0 -> g1_0 [label="A => AB"];
+    g0_0 -> g1_1 [label="A => AB"];
+    g1_0 -> g2_0 [label="A => AB"];
+    g1_0 -> g2_1 [label="A => AB"];
+    g1_1 -> g2_2 [label="B => A"];
+}
+"#
+        );
+
+        // The exported system keeps its own state.
+        assert_eq!(system.steps(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn derivation_dot_decompositions() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        let c = builder.token("C")?;
+        let d = builder.token("D")?;
+
+        builder.axiom(vec![a])?;
+        builder.production_rule(a, vec![a, b])?;
+        builder.decomposition_rule(b, vec![c, d])?;
+        builder.decomposition_rule(d, vec![c])?;
+
+        let system = builder.finish()?;
+
+        let mut dot = Vec::new();
+        export::write_derivation_dot(&system, 1, &mut dot)?;
+        let dot = String::from_utf8(dot).unwrap();
+
+        let edges = dot.lines().filter(|line| line.contains("->")).collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            vec![
+                r#"    g0_0 -> g1_0 [label="A => AB"];"#,
+                r#"    g0_0 -> g1_1 [label="A => AB\ndecomposed by B => CD"];"#,
+                r#"    g0_0 -> g1_2 [label="A => AB\ndecomposed by B => CD\ndecomposed by D => C"];"#,
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+//! Systems shared by the tests of several modules.
+
+use crate::{LSystem, LSystemBuilder, LSystemError};
+
+/// Lindenmayer's algae, `A => AB` and `B => A` from `A`.
+pub(crate) fn algae() -> Result<LSystem, LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![a, b])?;
+    builder.production_rule(b, vec![a])?;
+
+    builder.finish()
+}
+
+/// The fractal binary tree, `1 => 11` and `0 => 1[0]0` from `0`.
+pub(crate) fn binary_tree() -> Result<LSystem, LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    for name in ["0", "1"] {
+        builder.token(name)?;
+    }
+    let open = builder.token("[")?;
+    let close = builder.token("]")?;
+    builder.branch_tokens(open, close)?;
+
+    builder.parse_axiom("0")?;
+    builder.parse_rule("1 => 11")?;
+    builder.parse_rule("0 => 1[0]0")?;
+
+    builder.finish()
+}
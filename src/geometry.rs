@@ -0,0 +1,266 @@
+use crate::errors::LSystemError;
+use crate::system::LSystem;
+use crate::turtle::{Interpretation, Point};
+
+/// The smallest axis-aligned rectangle containing every drawn segment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+    pub fn width(&self) -> f64 {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> f64 {
+        self.max.y - self.min.y
+    }
+
+    pub fn area(&self) -> f64 {
+        self.width() * self.height()
+    }
+}
+
+/// Counts of values falling into bins of equal width, the first one starting
+/// at `0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub bin_width: f64,
+    pub counts: Vec<usize>,
+}
+
+/// The endpoints of every drawn segment.
+fn points(interpretation: &Interpretation) -> impl Iterator<Item = Point> + '_ {
+    interpretation.segments.iter().flat_map(|segment| [segment.from, segment.to])
+}
+
+/// The total length of the drawn segments.
+pub fn path_length(interpretation: &Interpretation) -> f64 {
+    interpretation.segments.iter().map(|segment| segment.length()).sum()
+}
+
+/// Returns the bounding box of the drawn segments, or `None` if nothing was
+/// drawn.
+pub fn bounding_box(interpretation: &Interpretation) -> Option<BoundingBox> {
+    points(interpretation).fold(None, |bounds, point| {
+        let BoundingBox { min, max } = bounds.unwrap_or(BoundingBox { min: point, max: point });
+
+        Some(BoundingBox {
+            min: Point::new(min.x.min(point.x), min.y.min(point.y)),
+            max: Point::new(max.x.max(point.x), max.y.max(point.y)),
+        })
+    })
+}
+
+/// Returns the convex hull of the drawn segments, counter-clockwise and
+/// without collinear points.
+pub fn convex_hull(interpretation: &Interpretation) -> Vec<Point> {
+    let mut points = points(interpretation).collect::<Vec<_>>();
+    points.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    points.dedup();
+
+    if points.len() < 3 {
+        return points;
+    }
+
+    // Andrew's monotone chain: the lower hull left to right, then the upper
+    // hull right to left.
+    let mut hull: Vec<Point> = Vec::with_capacity(points.len() + 1);
+    for point in points.iter() {
+        while hull.len() >= 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], point) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(*point);
+    }
+
+    let lower = hull.len() + 1;
+    for point in points.iter().rev().skip(1) {
+        while hull.len() >= lower && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], point) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(*point);
+    }
+
+    // The upper hull ends where the lower one started.
+    hull.pop();
+    hull
+}
+
+/// The area of the convex hull of the drawn segments.
+pub fn convex_hull_area(interpretation: &Interpretation) -> f64 {
+    let hull = convex_hull(interpretation);
+
+    let twice_area = (0..hull.len())
+        .map(|i| {
+            let (a, b) = (hull[i], hull[(i + 1) % hull.len()]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum::<f64>();
+
+    twice_area.abs() / 2.0
+}
+
+/// Returns the center of mass of the drawn segments, weighted by their
+/// length, or `None` if nothing of any length was drawn.
+pub fn centroid(interpretation: &Interpretation) -> Option<Point> {
+    let length = path_length(interpretation);
+    if length == 0.0 {
+        return None;
+    }
+
+    let (x, y) = interpretation.segments.iter().fold((0.0, 0.0), |(x, y), segment| {
+        let weight = segment.length();
+        (
+            x + weight * (segment.from.x + segment.to.x) / 2.0,
+            y + weight * (segment.from.y + segment.to.y) / 2.0,
+        )
+    });
+
+    Some(Point::new(x / length, y / length))
+}
+
+/// Returns the angle in degrees, between `0` and `180`, between the heading
+/// of the turtle at the base of every branch and the heading of the first
+/// segment the branch draws itself. Branches drawing nothing are skipped.
+///
+/// Fails if the brackets of the current state aren't balanced.
+pub fn branching_angles(system: &LSystem) -> Result<Vec<f64>, LSystemError> {
+    let tree = system.branch_tree()?;
+    let interpretation = system.interpret();
+
+    let mut draws = vec![false; system.get_state().len()];
+    for segment in interpretation.segments.iter() {
+        draws[segment.index] = true;
+    }
+
+    let mut angles = Vec::new();
+
+    for branch in tree.branches().iter() {
+        let Some(open) = branch.open() else {
+            continue;
+        };
+
+        // Skip the sub-branches the branch bears before drawing.
+        let mut index = branch.range.start;
+        while index < branch.range.end && !draws[index] {
+            index = match tree.matching(index) {
+                Some(close) => close + 1,
+                None => index + 1,
+            };
+        }
+
+        if index < branch.range.end {
+            let turn = interpretation.states[index].heading - interpretation.states[open].heading;
+            angles.push((turn + 180.0).rem_euclid(360.0) - 180.0);
+        }
+    }
+
+    Ok(angles.into_iter().map(f64::abs).collect())
+}
+
+/// Sorts angles between `0` and `180` degrees into `bins` bins of equal width.
+///
+/// # Panics
+///
+/// Panics if `bins` is `0`.
+pub fn angle_histogram(angles: &[f64], bins: usize) -> Histogram {
+    assert!(bins > 0, "a histogram needs at least one bin");
+
+    let bin_width = 180.0 / bins as f64;
+    let mut counts = vec![0; bins];
+
+    for angle in angles {
+        let bin = (angle / bin_width) as usize;
+        counts[bin.min(bins - 1)] += 1;
+    }
+
+    Histogram { bin_width, counts }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `c`, positive if it turns
+/// counter-clockwise.
+fn cross(a: &Point, b: &Point, c: &Point) -> f64 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LSystemBuilder, Turtle};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn geometry_of_forked_stem() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        for name in ["F", "+", "-"] {
+            builder.token(name)?;
+        }
+        let open = builder.token("[")?;
+        let close = builder.token("]")?;
+        builder.branch_tokens(open, close)?;
+        builder.parse_axiom("F[+F][-F]F")?;
+        builder.turtle(Turtle::new(1.0, 90.0));
+
+        let system = builder.finish()?;
+        let interpretation = system.interpret();
+
+        assert_close(path_length(&interpretation), 4.0);
+
+        let bounds = bounding_box(&interpretation).unwrap();
+        assert_close(bounds.min.x, -1.0);
+        assert_close(bounds.max.y, 2.0);
+        assert_close(bounds.area(), 4.0);
+
+        assert_eq!(convex_hull(&interpretation).len(), 4);
+        assert_close(convex_hull_area(&interpretation), 2.0);
+
+        let center = centroid(&interpretation).unwrap();
+        assert_close(center.x, 0.0);
+        assert_close(center.y, 1.0);
+
+        let angles = branching_angles(&system)?;
+        assert_eq!(angles.len(), 2);
+        angles.iter().for_each(|angle| assert_close(*angle, 90.0));
+
+        assert_eq!(angle_histogram(&angles, 4).counts, [0, 0, 2, 0]);
+        assert_eq!(angle_histogram(&[0.0, 180.0], 3).counts, [1, 0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn branching_angles_with_declared_branch_tokens() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+        for name in ["F", "+", "-"] {
+            builder.token(name)?;
+        }
+        let open = builder.token("(")?;
+        let close = builder.token(")")?;
+        builder.branch_tokens(open, close)?;
+        builder.parse_axiom("F(+F)(-F)F")?;
+
+        let system = builder.finish()?;
+        assert_close(bounding_box(&system.interpret()).unwrap().max.y, 2.0);
+
+        let angles = branching_angles(&system)?;
+        assert_eq!(angles.len(), 2);
+        angles.iter().for_each(|angle| assert_close(*angle, 90.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn geometry_of_nothing() {
+        let interpretation = Interpretation::default();
+
+        assert_eq!(path_length(&interpretation), 0.0);
+        assert_eq!(bounding_box(&interpretation), None);
+        assert_eq!(convex_hull_area(&interpretation), 0.0);
+        assert_eq!(centroid(&interpretation), None);
+    }
+}
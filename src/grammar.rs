@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::{Arc, Mutex};
+
+use crate::Arena;
+use crate::classification::Classification;
+use crate::environment::ModuleKind;
+use crate::production::ProductionFn;
+use crate::provenance::GenerationProvenance;
+use crate::token::TokenId;
+use crate::turtle::Turtle;
+
+/// The successor of some tokens after `2^i` steps.
+pub(crate) type Power = HashMap<TokenId, Arc<[TokenId]>>;
+
+/// Number of tokens the cache of powers holds before it is cleared.
+const MAX_CACHED_POWER_LEN: usize = 1 << 22;
+
+/// Powers of the rules computed so far, by token and exponent.
+#[derive(Debug, Default)]
+struct Powers {
+    entries: HashMap<(TokenId, u32), Arc<[TokenId]>>,
+    len: usize,
+}
+
+impl Powers {
+    fn insert(&mut self, key: (TokenId, u32), power: Arc<[TokenId]>) {
+        if power.len() > MAX_CACHED_POWER_LEN {
+            return;
+        }
+        if self.len + power.len() > MAX_CACHED_POWER_LEN {
+            self.entries.clear();
+            self.len = 0;
+        }
+
+        self.len += power.len();
+        self.entries.insert(key, power);
+    }
+}
+
+/// The immutable definition of an L-system: its tokens, axiom and rules.
+///
+/// A grammar is shared between every [`LSystem`](crate::LSystem) simulating
+/// it, so independent simulations, possibly on different threads, only own
+/// their state. Get one from a system built by
+/// [`LSystemBuilder`](crate::LSystemBuilder) with
+/// [`LSystem::grammar`](crate::LSystem::grammar), and start new simulations
+/// with [`LSystem::from_grammar`](crate::LSystem::from_grammar).
+#[derive(Debug)]
+pub struct Grammar {
+    arena: Arena,
+    axiom: Vec<TokenId>,
+    rules_map: HashMap<TokenId, Vec<TokenId>>,
+    production_fns: HashMap<TokenId, ProductionFn>,
+    rule_indices: HashMap<TokenId, usize>,
+    decompositions: HashMap<TokenId, Vec<TokenId>>,
+    decomposition_indices: HashMap<TokenId, usize>,
+    decomposition_depth: usize,
+    /// Filled in on demand by [`LSystem::step_by`](crate::LSystem::step_by).
+    powers: Mutex<Powers>,
+    turtle: Turtle,
+    branches: Option<(TokenId, TokenId)>,
+    open: bool,
+    seed: u64,
+}
+
+impl Grammar {
+    pub(crate) fn new(
+        arena: Arena,
+        axiom: Vec<TokenId>,
+        rules_map: HashMap<TokenId, Vec<TokenId>>,
+    ) -> Self {
+        let open = arena.iter_tokens().any(|token| ModuleKind::of(token).is_some());
+
+        Self {
+            arena,
+            axiom,
+            rules_map,
+            production_fns: HashMap::new(),
+            rule_indices: HashMap::new(),
+            decompositions: HashMap::new(),
+            decomposition_indices: HashMap::new(),
+            decomposition_depth: 0,
+            powers: Mutex::new(Powers::default()),
+            turtle: Turtle::default(),
+            branches: None,
+            open,
+            seed: 0,
+        }
+    }
+
+    pub(crate) fn with_production_fns(mut self, production_fns: HashMap<TokenId, ProductionFn>) -> Self {
+        self.production_fns = production_fns;
+        self
+    }
+
+    pub(crate) fn with_rule_indices(mut self, rule_indices: HashMap<TokenId, usize>) -> Self {
+        self.rule_indices = rule_indices;
+        self
+    }
+
+    pub(crate) fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub(crate) fn with_decompositions(
+        mut self,
+        decompositions: HashMap<TokenId, Vec<TokenId>>,
+        decomposition_indices: HashMap<TokenId, usize>,
+        depth: usize,
+    ) -> Self {
+        self.decompositions = decompositions;
+        self.decomposition_indices = decomposition_indices;
+        self.decomposition_depth = depth;
+        self
+    }
+
+    pub(crate) fn with_branches(mut self, branches: Option<(TokenId, TokenId)>) -> Self {
+        self.branches = branches;
+        self
+    }
+
+    pub(crate) fn with_turtle(mut self, turtle: Turtle) -> Self {
+        self.turtle = turtle;
+        self
+    }
+
+    pub fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    pub fn axiom(&self) -> &[TokenId] {
+        &self.axiom
+    }
+
+    /// The seed simulations start from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn turtle(&self) -> &Turtle {
+        &self.turtle
+    }
+
+    /// The tokens opening and closing a branch, as declared on the builder.
+    pub fn branch_tokens(&self) -> Option<(TokenId, TokenId)> {
+        self.branches
+    }
+
+    /// Whether the grammar has query or communication modules.
+    pub(crate) fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Whether every token is rewritten by a fixed successor, which makes the
+    /// system a deterministic, context-free one.
+    pub fn is_deterministic(&self) -> bool {
+        self.production_fns.is_empty()
+    }
+
+    pub(crate) fn production_fn(&self, id: &TokenId) -> Option<&ProductionFn> {
+        self.production_fns.get(id)
+    }
+
+    /// The fixed successor of `id`, before decomposition.
+    pub(crate) fn successor(&self, id: &TokenId) -> &[TokenId] {
+        &self.rules_map[id]
+    }
+
+    pub(crate) fn rule_index(&self, id: &TokenId) -> Option<usize> {
+        self.rule_indices.get(id).copied()
+    }
+
+    /// Applies decomposition rules to `tokens` until none of them match or the
+    /// maximum decomposition depth is reached. Decomposed tokens keep the
+    /// derivation of the token they were decomposed from, followed by the
+    /// decomposition rule applied to it.
+    pub(crate) fn decompose(&self, mut tokens: Vec<TokenId>, mut derivation: Option<&mut GenerationProvenance>) -> Vec<TokenId> {
+        if self.decompositions.is_empty() {
+            return tokens;
+        }
+
+        for _ in 0..self.decomposition_depth {
+            if !tokens.iter().any(|id| self.decompositions.contains_key(id)) {
+                break;
+            }
+
+            let mut decomposed = Vec::with_capacity(tokens.len());
+            let mut next_derivation = derivation
+                .as_ref()
+                .map(|_| GenerationProvenance::with_capacity(tokens.len()));
+
+            for (index, id) in tokens.iter().enumerate() {
+                let before = decomposed.len();
+
+                match self.decompositions.get(id) {
+                    Some(successor) => decomposed.extend_from_slice(successor),
+                    None => decomposed.push(*id),
+                }
+
+                if let (Some(next), Some(current)) = (next_derivation.as_mut(), derivation.as_deref()) {
+                    let mut decompositions = current.decompositions[index].clone();
+                    decompositions.extend(self.decomposition_indices.get(id));
+                    next.record(current.parents[index], current.rules[index], &decompositions, decomposed.len() - before);
+                }
+            }
+
+            tokens = decomposed;
+            if let (Some(current), Some(next)) = (derivation.as_deref_mut(), next_derivation) {
+                *current = next;
+            }
+        }
+
+        tokens
+    }
+
+    /// Returns what `id` becomes after one step, decompositions included, or
+    /// `None` if it is rewritten by a production function.
+    pub(crate) fn successor_of(&self, id: TokenId) -> Option<Vec<TokenId>> {
+        if self.production_fns.contains_key(&id) {
+            return None;
+        }
+
+        Some(self.decompose(self.rules_map[&id].clone(), None))
+    }
+
+    /// Returns the successor after `2^exponent` steps of every token occurring
+    /// in `tokens`, or `None` if one of them is longer than `limit`. Powers
+    /// are computed on demand, by applying the previous one twice, and cached
+    /// up to a bound. Only meaningful for deterministic grammars.
+    ///
+    /// The cache is only locked to look powers up and store them, so threads
+    /// compute in parallel, possibly the same power twice.
+    pub(crate) fn power(&self, tokens: &[TokenId], exponent: u32, limit: usize) -> Option<Power> {
+        let mut power = HashMap::new();
+
+        for &id in tokens {
+            if let Entry::Vacant(entry) = power.entry(id) {
+                entry.insert(self.power_of(id, exponent, limit)?);
+            }
+        }
+
+        Some(power)
+    }
+
+    fn power_of(&self, id: TokenId, exponent: u32, limit: usize) -> Option<Arc<[TokenId]>> {
+        let cached = self.powers
+            .lock()
+            .expect("power cache poisoned")
+            .entries
+            .get(&(id, exponent))
+            .cloned();
+        if let Some(power) = cached {
+            return (power.len() <= limit).then_some(power);
+        }
+
+        let power: Arc<[TokenId]> = match exponent.checked_sub(1) {
+            None => self.successor_of(id).expect("deterministic grammar").into(),
+            Some(half) => {
+                let mut power = Vec::new();
+                for next in self.power_of(id, half, limit)?.iter() {
+                    power.extend_from_slice(&self.power_of(*next, half, limit)?);
+                    if power.len() > limit {
+                        return None;
+                    }
+                }
+                power.into()
+            }
+        };
+
+        self.powers.lock().expect("power cache poisoned").insert((id, exponent), power.clone());
+        (power.len() <= limit).then_some(power)
+    }
+
+    /// Renders the rule `predecessor` is rewritten by, e.g. `A => AB`.
+    pub(crate) fn rule_string(&self, predecessor: TokenId) -> String {
+        let name = |id: &TokenId| self.arena.get_token(id).map(|token| token.name()).unwrap_or_default();
+
+        let successor = match self.production_fns.get(&predecessor) {
+            Some(f) => format!("{:?}", f),
+            None => self.rules_map[&predecessor].iter().map(name).collect(),
+        };
+
+        format!("{} => {}", name(&predecessor), successor)
+    }
+
+    /// Renders the production rule numbered `index`, as in `Location::Rule`,
+    /// if it is the rule its predecessor is rewritten by.
+    pub(crate) fn rule_string_at(&self, index: usize) -> Option<String> {
+        let (&predecessor, _) = self.rule_indices.iter().find(|(_, &other)| other == index)?;
+
+        Some(self.rule_string(predecessor))
+    }
+
+    /// Renders the decomposition rule numbered `index`, as in
+    /// `Location::Decomposition`, if it is the one its predecessor is
+    /// decomposed by.
+    pub(crate) fn decomposition_string_at(&self, index: usize) -> Option<String> {
+        let (predecessor, _) = self.decomposition_indices.iter().find(|(_, &other)| other == index)?;
+        let name = |id: &TokenId| self.arena.get_token(id).map(|token| token.name()).unwrap_or_default();
+        let successor = self.decompositions[predecessor].iter().map(name).collect::<String>();
+
+        Some(format!("{} => {}", name(predecessor), successor))
+    }
+
+    /// Describes which family of L-systems this grammar belongs to.
+    pub fn classify(&self) -> Classification {
+        let deterministic = self.is_deterministic().then_some(true);
+
+        // Production functions may return anything given any context, so
+        // only fixed rules tell anything.
+        let erasing = self.arena
+            .enumerate()
+            .any(|(id, _)| self.successor_of(id).is_some_and(|successor| successor.is_empty()));
+        let propagating = if erasing {
+            Some(false)
+        } else {
+            deterministic
+        };
+
+        let parametric = self.arena.iter_tokens().any(|token| token.param() > 0);
+
+        // The constant rule of the open token itself doesn't count.
+        let bracketed = self.branches.is_some_and(|(open, _)| {
+            self.axiom.contains(&open)
+                || self.rules_map
+                    .iter()
+                    .any(|(id, successor)| *id != open && successor.contains(&open))
+                || self.decompositions.values().any(|successor| successor.contains(&open))
+        });
+
+        Classification {
+            deterministic,
+            propagating,
+            context_free: deterministic,
+            parametric,
+            bracketed,
+        }
+    }
+}
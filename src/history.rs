@@ -0,0 +1,58 @@
+use std::collections::{HashMap, VecDeque};
+
+use rand::rngs::StdRng;
+
+use crate::token::TokenId;
+
+/// What it takes to restore a system to an earlier generation.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    pub(crate) steps: usize,
+    /// `None` when the state is the one of the previous checkpoint stepped
+    /// once by a deterministic system, in which case it is recomputed.
+    pub(crate) state: Option<Vec<TokenId>>,
+    /// Whether the checkpoint was taken before a step rather than an edit.
+    pub(crate) stepped: bool,
+    pub(crate) rng: StdRng,
+    /// `None` when the module values are recomputed along with the state.
+    pub(crate) values: Option<HashMap<usize, Vec<f64>>>,
+}
+
+/// The most recent checkpoints of a system, oldest first. The oldest one
+/// always holds its state.
+#[derive(Debug, Clone)]
+pub(crate) struct History {
+    pub(crate) capacity: usize,
+    pub(crate) checkpoints: VecDeque<Checkpoint>,
+}
+
+impl History {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn last(&self) -> Option<&Checkpoint> {
+        self.checkpoints.back()
+    }
+
+    /// Index of the latest checkpoint of `steps`.
+    pub(crate) fn position(&self, steps: usize) -> Option<usize> {
+        self.checkpoints.iter().rposition(|checkpoint| checkpoint.steps == steps)
+    }
+
+    /// Index of the latest checkpoint at or before `index` holding its state.
+    pub(crate) fn anchor(&self, index: usize) -> usize {
+        (0..=index)
+            .rev()
+            .find(|&i| self.checkpoints[i].state.is_some())
+            .expect("the oldest checkpoint holds its state")
+    }
+
+    /// The number of checkpoints over capacity.
+    pub(crate) fn overflow(&self) -> usize {
+        self.checkpoints.len().saturating_sub(self.capacity)
+    }
+}
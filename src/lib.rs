@@ -2,13 +2,36 @@ extern crate self as lsystem;
 
 pub use arena::{Arena};
 pub use builder::LSystemBuilder;
+pub use environment::Environment;
 pub use errors::LSystemError;
+pub use grammar::Grammar;
+pub use render::RenderOptions;
 pub use system::LSystem;
+pub use turtle::Turtle;
 
+pub mod analysis;
 pub mod arena;
+pub mod botany;
+pub mod branching;
 pub mod builder;
+pub mod classification;
+pub mod diagnostics;
+pub mod environment;
+pub mod equivalence;
 pub mod errors;
+pub mod expansion;
+pub mod export;
+#[cfg(test)]
+mod fixtures;
+pub mod geometry;
+pub mod grammar;
+mod history;
+pub mod lint;
+pub mod production;
+pub mod provenance;
+pub mod render;
 pub mod system;
 pub mod token;
+pub mod turtle;
 #[cfg(test)]
 mod tests;
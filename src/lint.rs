@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+
+use crate::builder::{LSystemBuilder, Location};
+use crate::token::TokenId;
+
+/// A likely mistake in a grammar, reported by [`LSystemBuilder::lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintWarning {
+    /// The token can never appear in any generation.
+    UnreachableToken { token: TokenId, name: String },
+    /// The predecessor of the rule never appears in any generation.
+    UnusedRule { location: Location, rule: String },
+    /// Every descendant of the token is eventually erased.
+    DeadToken { token: TokenId, name: String },
+    /// Several tokens share the same name and render identically.
+    DuplicateTokenName { name: String, tokens: Vec<TokenId> },
+    /// The rule is replaced by a later rule for the same predecessor.
+    ShadowedRule { location: Location, shadowed_by: Location, rule: String },
+}
+
+impl Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintWarning::UnreachableToken { name, .. } => {
+                write!(f, "token `{}` is unreachable from the axiom", name)
+            }
+            LintWarning::UnusedRule { location, rule } => {
+                write!(f, "{} `{}` is never applied", location, rule)
+            }
+            LintWarning::DeadToken { name, .. } => {
+                write!(f, "token `{}` always erases itself", name)
+            }
+            LintWarning::DuplicateTokenName { name, tokens } => {
+                write!(f, "{} tokens are named `{}`", tokens.len(), name)
+            }
+            LintWarning::ShadowedRule { location, shadowed_by, rule } => {
+                write!(f, "{} `{}` is shadowed by {}", location, rule, shadowed_by)
+            }
+        }
+    }
+}
+
+pub(crate) fn lint(builder: &LSystemBuilder) -> Vec<LintWarning> {
+    let arena = builder.arena();
+    let name = |id: &TokenId| arena.get_token(id).map(|token| token.name().to_string()).unwrap_or_default();
+
+    // The rules `finish` would keep, later rules replacing earlier ones.
+    let mut productions = HashMap::new();
+    let mut decompositions = HashMap::new();
+    let mut warnings = Vec::new();
+
+    for (location, rule) in builder.locations() {
+        let winners = match location {
+            Location::Decomposition(_) => &mut decompositions,
+            _ => &mut productions,
+        };
+
+        if let Some((previous, _)) = winners.insert(rule.predecessor, (location, rule)) {
+            warnings.push(LintWarning::ShadowedRule {
+                location: previous,
+                shadowed_by: location,
+                rule: builder.rule_string(previous),
+            });
+        }
+    }
+
+    // Successors of a token, over-approximated: production functions may
+    // produce any token, and decompositions add to what the token produces.
+    let successors = |id: &TokenId| -> Option<Vec<TokenId>> {
+        let mut successors = match productions.get(id) {
+            Some((_, rule)) => rule.successor.tokens()?.to_vec(),
+            None => vec![*id],
+        };
+        let mut pending = successors.clone();
+        let mut seen = HashSet::new();
+
+        while let Some(next) = pending.pop() {
+            if !seen.insert(next) {
+                continue;
+            }
+            if let Some((_, rule)) = decompositions.get(&next) {
+                let decomposed = rule.successor.tokens()?;
+                successors.extend_from_slice(decomposed);
+                pending.extend_from_slice(decomposed);
+            }
+        }
+
+        Some(successors)
+    };
+
+    if let Some(axiom) = builder.axiom_tokens() {
+        let mut reachable = HashSet::new();
+        let mut pending = axiom.to_vec();
+        let mut unknown = false;
+
+        while let Some(id) = pending.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            match successors(&id) {
+                Some(next) => pending.extend(next),
+                None => unknown = true,
+            }
+        }
+
+        if !unknown {
+            for (id, token) in arena.enumerate() {
+                if !reachable.contains(&id) {
+                    warnings.push(LintWarning::UnreachableToken {
+                        token: id,
+                        name: token.name().to_string(),
+                    });
+                }
+            }
+
+            for (location, rule) in builder.locations() {
+                if !reachable.contains(&rule.predecessor) {
+                    warnings.push(LintWarning::UnusedRule {
+                        location,
+                        rule: builder.rule_string(location),
+                    });
+                }
+            }
+        }
+    }
+
+    // A token is dead once everything it produces is dead, starting from
+    // tokens with empty successors.
+    let mut dead = HashSet::new();
+    loop {
+        let before = dead.len();
+
+        for (id, _) in arena.enumerate() {
+            if dead.contains(&id) {
+                continue;
+            }
+            if let Some(next) = successors(&id) {
+                if next.iter().all(|next| dead.contains(next)) {
+                    dead.insert(id);
+                }
+            }
+        }
+
+        if dead.len() == before {
+            break;
+        }
+    }
+
+    for (id, _) in arena.enumerate() {
+        if dead.contains(&id) {
+            warnings.push(LintWarning::DeadToken { token: id, name: name(&id) });
+        }
+    }
+
+    let mut names = BTreeMap::new();
+    for (id, token) in arena.enumerate() {
+        names.entry(token.name()).or_insert_with(Vec::new).push(id);
+    }
+    for (name, tokens) in names {
+        if tokens.len() > 1 {
+            warnings.push(LintWarning::DuplicateTokenName { name: name.to_string(), tokens });
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LSystemError;
+
+    #[test]
+    fn lint_reports_grammar_problems() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let b = builder.token("B")?;
+        let c = builder.token("C")?;
+        let d = builder.token("D")?;
+        let x = builder.token("X")?;
+        let x2 = builder.token("X")?;
+
+        builder.axiom(vec![a])?;
+        builder.production_rule(a, vec![a, a])?;
+        builder.production_rule(a, vec![a, b])?;
+        builder.production_rule(b, vec![c])?;
+        builder.production_rule(c, vec![])?;
+        builder.production_rule(d, vec![d, d])?;
+
+        let warnings = builder.lint();
+
+        assert_eq!(
+            warnings,
+            vec![
+                LintWarning::ShadowedRule {
+                    location: Location::Rule(0),
+                    shadowed_by: Location::Rule(1),
+                    rule: "A => AA".to_string(),
+                },
+                LintWarning::UnreachableToken { token: d.id(), name: "D".to_string() },
+                LintWarning::UnreachableToken { token: x.id(), name: "X".to_string() },
+                LintWarning::UnreachableToken { token: x2.id(), name: "X".to_string() },
+                LintWarning::UnusedRule { location: Location::Rule(4), rule: "D => DD".to_string() },
+                LintWarning::DeadToken { token: b.id(), name: "B".to_string() },
+                LintWarning::DeadToken { token: c.id(), name: "C".to_string() },
+                LintWarning::DuplicateTokenName { name: "X".to_string(), tokens: vec![x.id(), x2.id()] },
+            ]
+        );
+
+        assert_eq!(warnings[0].to_string(), "rule #0 `A => AA` is shadowed by rule #1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn lint_production_fn_is_opaque() -> Result<(), LSystemError> {
+        let mut builder = LSystemBuilder::new();
+
+        let a = builder.token("A")?;
+        let _b = builder.token("B")?;
+
+        builder.axiom(vec![a])?;
+        builder.production_fn(a, |_| vec![])?;
+
+        // `B` might be produced by the function, so it isn't reported.
+        assert!(builder.lint().is_empty());
+
+        Ok(())
+    }
+}
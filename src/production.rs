@@ -0,0 +1,123 @@
+use std::cell::{RefCell, RefMut};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+
+use crate::arena::Arena;
+use crate::token::{TokenHandle, TokenId};
+
+/// What a token is rewritten into: either a fixed sequence of tokens or the
+/// result of calling a production function.
+#[derive(Debug, Clone)]
+pub enum Successor {
+    Tokens(Vec<TokenId>),
+    Function(ProductionFn),
+}
+
+impl Successor {
+    pub fn tokens(&self) -> Option<&[TokenId]> {
+        match self {
+            Successor::Tokens(tokens) => Some(tokens),
+            Successor::Function(_) => None,
+        }
+    }
+}
+
+type BoxedProduction = dyn Fn(&Context) -> Vec<TokenHandle> + Send + Sync;
+
+/// A production computed in Rust rather than given as a fixed successor.
+#[derive(Clone)]
+pub struct ProductionFn(Arc<BoxedProduction>);
+
+impl ProductionFn {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Context) -> Vec<TokenHandle> + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+
+    pub fn call(&self, context: &Context) -> Vec<TokenHandle> {
+        (self.0)(context)
+    }
+}
+
+impl std::fmt::Debug for ProductionFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str("<fn>")
+    }
+}
+
+/// The module being rewritten by a [`ProductionFn`] and its surroundings.
+/// Tokens are handed out as handles of the system's arena, so that they can
+/// be returned by the function.
+pub struct Context<'a> {
+    arena: &'a Arena,
+    state: &'a [TokenId],
+    index: usize,
+    generation: usize,
+    values: &'a HashMap<usize, Vec<f64>>,
+    rng: RefCell<&'a mut StdRng>,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(
+        arena: &'a Arena,
+        state: &'a [TokenId],
+        index: usize,
+        generation: usize,
+        values: &'a HashMap<usize, Vec<f64>>,
+        rng: &'a mut StdRng,
+    ) -> Self {
+        Self {
+            arena,
+            state,
+            index,
+            generation,
+            values,
+            rng: RefCell::new(rng),
+        }
+    }
+
+    /// The token being rewritten.
+    pub fn token(&self) -> TokenHandle {
+        self.handle(self.state[self.index])
+    }
+
+    /// Position of the token in the state.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn left(&self) -> Option<TokenHandle> {
+        self.index.checked_sub(1).map(|index| self.handle(self.state[index]))
+    }
+
+    pub fn right(&self) -> Option<TokenHandle> {
+        self.state.get(self.index + 1).map(|id| self.handle(*id))
+    }
+
+    fn handle(&self, id: TokenId) -> TokenHandle {
+        self.arena.brand(id)
+    }
+
+    /// The whole state the token belongs to.
+    pub fn state(&self) -> &[TokenId] {
+        self.state
+    }
+
+    /// Number of steps taken before this one.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Parameter values of the token if it is a query or communication module.
+    pub fn values(&self) -> Option<&[f64]> {
+        self.values.get(&self.index).map(Vec::as_slice)
+    }
+
+    pub fn rng(&self) -> RefMut<'_, StdRng> {
+        RefMut::map(self.rng.borrow_mut(), |rng| &mut **rng)
+    }
+}
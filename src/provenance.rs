@@ -0,0 +1,114 @@
+/// How the tokens of one generation were derived from the previous one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GenerationProvenance {
+    /// For each token, the index of the token it was derived from.
+    pub parents: Vec<usize>,
+    /// For each token, the index of the production rule that produced it, as
+    /// in `Location::Rule`: rules are numbered in the order they were submitted
+    /// to the builder, rejected ones included. `None` marks tokens kept by the
+    /// implicit identity rule of constants.
+    pub rules: Vec<Option<usize>>,
+    /// For each token, the indices of the decomposition rules that turned the
+    /// token produced by its rule into it, in the order they were applied, as
+    /// in `Location::Decomposition`.
+    pub decompositions: Vec<Vec<usize>>,
+}
+
+impl GenerationProvenance {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            parents: Vec::with_capacity(capacity),
+            rules: Vec::with_capacity(capacity),
+            decompositions: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn record(&mut self, parent: usize, rule: Option<usize>, decompositions: &[usize], count: usize) {
+        self.parents.extend(std::iter::repeat_n(parent, count));
+        self.rules.extend(std::iter::repeat_n(rule, count));
+        self.decompositions.extend(std::iter::repeat_n(decompositions.to_vec(), count));
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+}
+
+/// Derivation records of every step taken since provenance tracking started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    start: usize,
+    generations: Vec<GenerationProvenance>,
+}
+
+impl Provenance {
+    pub(crate) fn new(start: usize) -> Self {
+        Self {
+            start,
+            generations: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, generation: GenerationProvenance) {
+        self.generations.push(generation);
+    }
+
+    /// Drops the records of the generations after `end`, restarting at `end`
+    /// if it is before the start.
+    pub(crate) fn truncate(&mut self, end: usize) {
+        match end.checked_sub(self.start) {
+            Some(len) => self.generations.truncate(len),
+            None => *self = Provenance::new(end),
+        }
+    }
+
+    /// The earliest generation lineages can be traced back to.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The latest generation with derivation records.
+    pub fn end(&self) -> usize {
+        self.start + self.generations.len()
+    }
+
+    /// Returns how `generation` was derived from the generation before it.
+    pub fn generation(&self, generation: usize) -> Option<&GenerationProvenance> {
+        let offset = generation.checked_sub(self.start + 1)?;
+        self.generations.get(offset)
+    }
+
+    pub fn parent(&self, generation: usize, index: usize) -> Option<usize> {
+        self.generation(generation)?.parents.get(index).copied()
+    }
+
+    pub fn rule(&self, generation: usize, index: usize) -> Option<Option<usize>> {
+        self.generation(generation)?.rules.get(index).copied()
+    }
+
+    pub fn decompositions(&self, generation: usize, index: usize) -> Option<&[usize]> {
+        self.generation(generation)?.decompositions.get(index).map(Vec::as_slice)
+    }
+
+    /// Walks the ancestors of the token at `index` of `generation`, returning
+    /// its index in each generation from `generation` back to [`Provenance::start`].
+    pub fn lineage(&self, generation: usize, index: usize) -> Option<Vec<usize>> {
+        if generation < self.start || generation > self.end() {
+            return None;
+        }
+
+        let mut lineage = vec![index];
+        let mut current = index;
+
+        for generation in (self.start + 1..=generation).rev() {
+            current = self.parent(generation, current)?;
+            lineage.push(current);
+        }
+
+        Some(lineage)
+    }
+}
@@ -0,0 +1,75 @@
+use std::io::{BufWriter, Write};
+
+use crate::Arena;
+use crate::errors::LSystemError;
+use crate::token::TokenId;
+
+/// How [`LSystem::render_to`](crate::LSystem::render_to) writes a state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    separator: String,
+    parameters: bool,
+}
+
+impl RenderOptions {
+    /// Writes token names back to back, without parameters, as
+    /// [`LSystem::render`](crate::LSystem::render) does.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `separator` between tokens.
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Writes the parameter values of modules as `name(x,y)`, for modules
+    /// whose values are known.
+    pub fn parameters(mut self, enabled: bool) -> Self {
+        self.parameters = enabled;
+        self
+    }
+}
+
+/// Writes `tokens` to `writer`, looking up the parameter values of the token
+/// at each index with `values`. Writes are buffered, so that unbuffered
+/// writers such as files aren't written to once per token.
+pub(crate) fn write_tokens<'a, W, I, V>(
+    writer: &mut W,
+    arena: &Arena,
+    tokens: I,
+    values: V,
+    options: &RenderOptions,
+) -> Result<(), LSystemError>
+where
+    W: Write,
+    I: IntoIterator<Item = TokenId>,
+    V: Fn(usize) -> Option<&'a [f64]>,
+{
+    let mut writer = BufWriter::new(writer);
+
+    for (index, id) in tokens.into_iter().enumerate() {
+        let token = arena.get_token(&id).ok_or(LSystemError::InvalidTokenId(id))?;
+
+        if index > 0 {
+            writer.write_all(options.separator.as_bytes())?;
+        }
+        writer.write_all(token.name().as_bytes())?;
+
+        if let Some(values) = values(index).filter(|_| options.parameters) {
+            write!(writer, "(")?;
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(writer, ",")?;
+                }
+                write!(writer, "{}", value)?;
+            }
+            write!(writer, ")")?;
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
@@ -1,66 +1,804 @@
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 use crate::Arena;
-use crate::token::TokenId;
+use crate::analysis::{self, BigUint};
+use crate::branching::BranchTree;
+use crate::builder::{is_balanced, render_tokens};
+use crate::classification::Classification;
+use crate::environment::{Environment, ModuleKind, Query};
+use crate::errors::LSystemError;
+use crate::expansion::Expansion;
+use crate::grammar::Grammar;
+use crate::history::{Checkpoint, History};
+use crate::production::Context;
+use crate::provenance::{GenerationProvenance, Provenance};
+use crate::render::{self, RenderOptions};
+use crate::token::{TokenHandle, TokenId};
+use crate::turtle::Interpretation;
+
+/// A generation repeating an earlier one, found by [`LSystem::step_until_stable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cycle {
+    /// The first generation of the cycle.
+    pub pre_period: usize,
+    /// The number of generations after which states repeat; `1` for fixed points.
+    pub period: usize,
+}
+
+/// A generation of a system, yielded by [`LSystem::generations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    /// The number of steps taken to reach this generation.
+    pub step: usize,
+    /// Shared with the system it was taken from, which only copies it if
+    /// edited while the generation is still held.
+    pub state: Arc<Vec<TokenId>>,
+}
+
+impl Generation {
+    pub fn len(&self) -> usize {
+        self.state.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.is_empty()
+    }
+}
+
+/// Iterator stepping a system, see [`LSystem::generations`].
+#[derive(Debug)]
+pub struct Generations<'a> {
+    system: &'a mut LSystem,
+    started: bool,
+}
 
+impl Iterator for Generations<'_> {
+    type Item = Generation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.started {
+            self.system.step();
+        }
+        self.started = true;
+
+        Some(Generation {
+            step: self.system.steps,
+            state: self.system.state.clone(),
+        })
+    }
+}
+
+/// A simulation of a [`Grammar`]: the current state and everything that
+/// evolves with it. Cloning a system shares its grammar.
 #[derive(Clone, Debug)]
 pub struct LSystem {
-    arena: Arena,
-    axiom: Vec<TokenId>,
-    rules_map: HashMap<TokenId, Vec<TokenId>>,
-    state: Vec<TokenId>,
+    grammar: Arc<Grammar>,
+    values: HashMap<usize, Vec<f64>>,
+    seed: u64,
+    rng: StdRng,
+    provenance: Option<Provenance>,
+    history: Option<History>,
+    state: Arc<Vec<TokenId>>,
     steps: usize,
 }
 
 impl LSystem {
-    pub(crate) fn new(
-        arena: Arena,
-        axiom: Vec<TokenId>,
-        rules_map: HashMap<TokenId, Vec<TokenId>>,
-    ) -> Self {
-        Self {
-            arena,
-            axiom: axiom.clone(),
-            rules_map,
-            state: axiom,
+    /// Starts a simulation of `grammar` from its axiom.
+    pub fn from_grammar(grammar: Arc<Grammar>) -> Self {
+        let seed = grammar.seed();
+
+        let mut system = Self {
+            state: Arc::new(grammar.axiom().to_vec()),
+            grammar,
+            values: HashMap::new(),
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            provenance: None,
+            history: None,
             steps: 0,
-        }
+        };
+        system.refresh_queries();
+
+        system
+    }
+
+    /// The definition this system simulates, which can be shared with other
+    /// simulations.
+    pub fn grammar(&self) -> &Arc<Grammar> {
+        &self.grammar
+    }
+
+    /// Changes the seed the random number generator is reset to, and reseeds
+    /// it, so that simulations of one grammar can be independent.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
     }
 
     pub fn reset(&mut self) {
-        self.state = self.axiom.clone();
+        self.state = Arc::new(self.grammar.axiom().to_vec());
         self.steps = 0;
+        self.rng = StdRng::seed_from_u64(self.seed);
+        if self.provenance.is_some() {
+            self.provenance = Some(Provenance::new(0));
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.checkpoints.clear();
+        }
+        self.refresh_queries();
+    }
+
+    /// Starts or stops recording which token of the previous generation each
+    /// token was derived from. Lineages can be traced back to the generation
+    /// current when tracking was enabled.
+    pub fn track_provenance(&mut self, enabled: bool) {
+        self.provenance = match (enabled, self.provenance.take()) {
+            (true, Some(provenance)) => Some(provenance),
+            (true, None) => Some(Provenance::new(self.steps)),
+            (false, _) => None,
+        };
     }
 
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// Steps the system.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a production function returns an id that isn't part of this
+    /// system's arena, or branch tokens that don't balance; use
+    /// [`LSystem::try_step`] to handle those cases.
     pub fn step(&mut self) {
+        self.try_step().expect("production function returned an invalid successor");
+    }
+
+    /// Steps the system, failing if a production function returns an id that
+    /// isn't part of this system's arena, or a successor whose branch tokens
+    /// don't balance. The system is left as it was on failure.
+    pub fn try_step(&mut self) -> Result<(), LSystemError> {
+        self.rewrite(None)
+    }
+
+    /// Steps the system and lets `environment` supply the values of the
+    /// communication modules in the resulting state.
+    ///
+    /// Fails if the environment responds with a different number of values
+    /// than a module's arity, in which case the system is left as it was.
+    pub fn step_with<E: Environment>(&mut self, environment: &mut E) -> Result<(), LSystemError> {
+        self.rewrite(Some(environment))
+    }
+
+    /// Fills in query modules only, which can't fail.
+    fn refresh_queries(&mut self) {
+        self.values = self.module_values_of(&self.state, self.steps, None)
+            .expect("query modules are filled without an environment");
+    }
+
+    /// Rewrites the state and fills in its modules. Nothing changes unless
+    /// both succeed.
+    fn rewrite(&mut self, environment: Option<&mut dyn Environment>) -> Result<(), LSystemError> {
+        let checkpoint = self.history.is_some().then(|| self.checkpoint(true));
+        let rng = self.rng.clone();
         let mut next_state = Vec::new();
+        let mut derivation = self.provenance
+            .as_ref()
+            .map(|_| GenerationProvenance::with_capacity(self.state.len()));
+
+        if self.grammar.is_deterministic() && derivation.is_none() {
+            for id in self.state.iter() {
+                next_state.extend_from_slice(self.grammar.successor(id));
+            }
+        } else {
+            for (index, id) in self.state.iter().enumerate() {
+                let before = next_state.len();
 
-        for id in self.state.iter() {
-            next_state.extend(self.rules_map[id].clone());
+                match self.grammar.production_fn(id) {
+                    Some(f) => {
+                        let arena = self.grammar.arena();
+                        let context = Context::new(arena, &self.state, index, self.steps, &self.values, &mut self.rng);
+                        let successor = f.call(&context);
+
+                        if let Some(&invalid) = successor.iter().find(|handle| !arena.owns(handle)) {
+                            self.rng = rng;
+                            return Err(arena.invalid_id(invalid));
+                        }
+
+                        let successor = successor.iter().map(TokenHandle::id).collect::<Vec<_>>();
+                        if let Some((open, close)) = self.grammar.branch_tokens() {
+                            if !is_balanced(&successor, open, close) {
+                                self.rng = rng;
+                                let rule = format!("{} => {}", render_tokens(arena, &[*id]), render_tokens(arena, &successor));
+                                return Err(LSystemError::UnbalancedBranches(rule));
+                            }
+                        }
+
+                        next_state.extend(successor);
+                    }
+                    None => next_state.extend_from_slice(self.grammar.successor(id)),
+                }
+
+                if let Some(derivation) = derivation.as_mut() {
+                    let rule = self.grammar.rule_index(id);
+                    derivation.record(index, rule, &[], next_state.len() - before);
+                }
+            }
         }
 
-        self.state = next_state;
+        let next_state = self.grammar.decompose(next_state, derivation.as_mut());
+        let values = match self.module_values_of(&next_state, self.steps + 1, environment) {
+            Ok(values) => values,
+            Err(error) => {
+                self.rng = rng;
+                return Err(error);
+            }
+        };
+
+        if let Some(checkpoint) = checkpoint {
+            self.record(checkpoint);
+        }
+
+        self.state = Arc::new(next_state);
+        self.values = values;
         self.steps += 1;
+
+        if let (Some(provenance), Some(derivation)) = (self.provenance.as_mut(), derivation) {
+            provenance.push(derivation);
+        }
+
+        Ok(())
+    }
+
+    /// The parameters of the modules of `state` after `steps` steps: query
+    /// modules get the turtle position and, given an environment, communication
+    /// modules get its response.
+    fn module_values_of(
+        &self,
+        state: &[TokenId],
+        steps: usize,
+        mut environment: Option<&mut dyn Environment>,
+    ) -> Result<HashMap<usize, Vec<f64>>, LSystemError> {
+        let mut values = HashMap::new();
+
+        if !self.grammar.is_open() {
+            return Ok(values);
+        }
+
+        let interpretation = self.grammar.turtle().interpret(self.grammar.arena(), state, self.grammar.branch_tokens());
+
+        for (index, id) in state.iter().enumerate() {
+            let Some(token) = self.grammar.arena().get_token(id) else {
+                continue;
+            };
+            let turtle = interpretation.states[index];
+
+            match ModuleKind::of(token) {
+                Some(ModuleKind::Query) => {
+                    values.insert(index, vec![turtle.position.x, turtle.position.y]);
+                }
+                Some(ModuleKind::Communication) => {
+                    if let Some(environment) = environment.as_deref_mut() {
+                        let query = Query {
+                            token,
+                            index,
+                            turtle,
+                            steps,
+                        };
+                        let response = environment.respond(&query);
+
+                        if response.len() != token.param() as usize {
+                            return Err(LSystemError::ArityMismatch {
+                                name: token.name().to_string(),
+                                expected: token.param() as usize,
+                                found: response.len(),
+                            });
+                        }
+
+                        values.insert(index, response);
+                    }
+                }
+                None => {}
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// See [`Grammar::is_deterministic`].
+    pub fn is_deterministic(&self) -> bool {
+        self.grammar.is_deterministic()
+    }
+
+    /// Counts how many times each token, in arena order, appears `n` steps
+    /// after the axiom, see [`analysis::token_counts`].
+    pub fn token_counts(&self, n: usize) -> Result<Vec<BigUint>, LSystemError> {
+        analysis::token_counts(self, n)
+    }
+
+    /// See [`Grammar::branch_tokens`].
+    pub fn branch_tokens(&self) -> Option<(TokenId, TokenId)> {
+        self.grammar.branch_tokens()
     }
 
+    /// Runs the turtle of the grammar over the current state.
+    pub fn interpret(&self) -> Interpretation {
+        self.grammar.turtle().interpret(self.grammar.arena(), &self.state, self.grammar.branch_tokens())
+    }
+
+    /// Parses the current state into its tree of branches. Without branch
+    /// tokens the whole state is a single branch.
+    ///
+    /// Fails with the index of the first bracket without a match if the
+    /// brackets of the state aren't balanced. Rules can't cause this: the
+    /// builder rejects rules that unbalance the brackets or rewrite one, and
+    /// steps fail on production functions that do. Edits of the state can.
+    pub fn branch_tree(&self) -> Result<BranchTree, LSystemError> {
+        BranchTree::parse(&self.state, self.grammar.branch_tokens()).map_err(LSystemError::UnmatchedBracket)
+    }
+
+    /// See [`Grammar::classify`].
+    pub fn classify(&self) -> Classification {
+        self.grammar.classify()
+    }
+
+    /// Takes `n` steps. Deterministic systems jump ahead using the successor
+    /// of the tokens of the state after `2^i` steps, as long as those are no
+    /// longer than the state itself, and take single steps otherwise. These
+    /// powers are computed on first use and cached in the grammar, shared
+    /// with every simulation of it.
+    ///
+    /// Systems with production functions or tracking provenance are stepped
+    /// one generation at a time.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`LSystem::step`].
     pub fn step_by(&mut self, n: usize) {
-        for _ in 0..n {
+        if !self.grammar.is_deterministic() || self.provenance.is_some() {
+            for _ in 0..n {
+                self.step();
+            }
+            return;
+        }
+
+        let jumped = if self.history.is_some() {
+            self.record_jump(n)
+        } else {
+            None
+        };
+
+        let (state, rest) = match jumped {
+            Some((steps, state)) => (state, n - steps),
+            None => (Arc::unwrap_or_clone(std::mem::take(&mut self.state)), n),
+        };
+        self.state = Arc::new(self.advance(state, rest));
+        self.steps += n;
+        self.refresh_queries();
+    }
+
+    /// Rewrites `state` of a deterministic system `n` times, using the powers
+    /// of the rules cached in the grammar. Powers are only looked up for the
+    /// distinct tokens of the state, so each jump goes over the state once.
+    fn advance(&self, mut state: Vec<TokenId>, n: usize) -> Vec<TokenId> {
+        let mut rest = n;
+        let mut tokens = distinct(&state);
+
+        while rest > 0 {
+            // The largest jump left whose powers don't outgrow the state, so
+            // that the cache stays in proportion to the states it advances.
+            let mut jump = None;
+            let mut exponent = 0;
+            while 1usize.checked_shl(exponent).is_some_and(|steps| steps <= rest) {
+                match self.grammar.power(&tokens, exponent, state.len()) {
+                    Some(power) => jump = Some((exponent, power)),
+                    None => break,
+                }
+                exponent += 1;
+            }
+
+            let (exponent, power) = jump.unwrap_or_else(|| {
+                (0, self.grammar.power(&tokens, 0, usize::MAX).expect("single steps have no limit"))
+            });
+
+            // Tokens are looked up by value, which is unique within an arena.
+            let mut successors: [&[TokenId]; 128] = [&[]; 128];
+            for (id, successor) in &power {
+                successors[id.value() as usize] = successor;
+            }
+
+            let len = state.iter().map(|id| successors[id.value() as usize].len()).sum();
+            let mut next = Vec::with_capacity(len);
+            for id in &state {
+                next.extend_from_slice(successors[id.value() as usize]);
+            }
+
+            // The tokens of the next state are those the powers produce.
+            tokens = distinct(&power.values().flat_map(|successor| successor.iter().copied()).collect::<Vec<_>>());
+            state = next;
+            rest -= 1 << exponent;
+        }
+
+        state
+    }
+
+    /// Keeps the last `capacity` generations and recorded edits, so that they
+    /// can be looked up with [`LSystem::generation`] and undone with
+    /// [`LSystem::step_back`] without stepping again from the axiom.
+    ///
+    /// Deterministic systems only keep the states they can't recompute on
+    /// demand. Other systems keep every state along with the random number
+    /// generator, so that stepping again after undoing is exact. A capacity of
+    /// `0` stops keeping history.
+    pub fn keep_history(&mut self, capacity: usize) {
+        if capacity == 0 {
+            self.history = None;
+            return;
+        }
+
+        self.history.get_or_insert_with(|| History::new(capacity)).capacity = capacity;
+        self.evict();
+    }
+
+    /// The number of steps and edits that can be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.checkpoints.len())
+    }
+
+    /// Undoes the last step or recorded edit. Returns `false` if there is
+    /// nothing left to undo.
+    ///
+    /// Values supplied by an environment are only restored for systems that
+    /// keep their states, others refill their query modules only.
+    pub fn step_back(&mut self) -> bool {
+        let Some(index) = self.history_len().checked_sub(1) else {
+            return false;
+        };
+
+        let state = self.checkpoint_state(index);
+        let history = self.history.as_mut().expect("history is kept");
+        let checkpoint = history.checkpoints.pop_back().expect("history isn't empty");
+
+        self.state = Arc::new(state);
+        self.steps = checkpoint.steps;
+        self.rng = checkpoint.rng;
+        match checkpoint.values {
+            Some(values) => self.values = values,
+            None => self.refresh_queries(),
+        }
+        if let Some(provenance) = self.provenance.as_mut() {
+            provenance.truncate(self.steps);
+        }
+
+        true
+    }
+
+    /// Returns the generation reached after `step` steps, if it is the
+    /// current one or still in the history. A generation that was edited is
+    /// returned as it was last stepped from.
+    pub fn generation(&self, step: usize) -> Option<Generation> {
+        if step == self.steps {
+            return Some(Generation {
+                step,
+                state: self.state.clone(),
+            });
+        }
+
+        let index = self.history.as_ref()?.position(step)?;
+
+        Some(Generation {
+            step,
+            state: Arc::new(self.checkpoint_state(index)),
+        })
+    }
+
+    /// Captures the current generation before it is stepped or edited.
+    fn checkpoint(&self, stepped: bool) -> Checkpoint {
+        let recomputable = self.grammar.is_deterministic()
+            && self.history
+                .as_ref()
+                .and_then(History::last)
+                .is_some_and(|last| last.stepped && last.steps + 1 == self.steps);
+
+        Checkpoint {
+            steps: self.steps,
+            state: (!recomputable).then(|| self.state.to_vec()),
+            stepped,
+            rng: self.rng.clone(),
+            values: Some(self.values.clone()),
+        }
+    }
+
+    /// Records checkpoints for the generations `step_by` jumps over, as many
+    /// as fit in the history. If the first of them is ahead of the current
+    /// generation, returns how many steps ahead it is along with its state, so
+    /// that the jump can carry on from there.
+    fn record_jump(&mut self, n: usize) -> Option<(usize, Vec<TokenId>)> {
+        let capacity = self.history.as_ref().map_or(0, |history| history.capacity);
+        let first = n.saturating_sub(capacity);
+        let mut jumped = None;
+
+        for offset in first..n {
+            let checkpoint = if offset == 0 {
+                self.checkpoint(true)
+            } else {
+                let state = (offset == first).then(|| self.advance(self.state.to_vec(), offset));
+                if let Some(state) = &state {
+                    jumped = Some((offset, state.clone()));
+                }
+
+                Checkpoint {
+                    steps: self.steps + offset,
+                    state,
+                    stepped: true,
+                    rng: self.rng.clone(),
+                    values: None,
+                }
+            };
+
+            self.record(checkpoint);
+        }
+
+        jumped
+    }
+
+    fn record(&mut self, checkpoint: Checkpoint) {
+        if let Some(history) = self.history.as_mut() {
+            history.checkpoints.push_back(checkpoint);
+            self.evict();
+        }
+    }
+
+    /// Drops the checkpoints over capacity, keeping the state of the oldest
+    /// remaining one.
+    fn evict(&mut self) {
+        let Some(history) = self.history.as_ref() else {
+            return;
+        };
+
+        let overflow = history.overflow();
+        if overflow == 0 {
+            return;
+        }
+
+        let state = history.checkpoints[overflow]
+            .state
+            .is_none()
+            .then(|| self.checkpoint_state(overflow));
+
+        let history = self.history.as_mut().expect("history is kept");
+        history.checkpoints.drain(..overflow);
+        if let Some(state) = state {
+            history.checkpoints[0].state = Some(state);
+        }
+    }
+
+    /// The state of the checkpoint at `index`, recomputed from the latest
+    /// checkpoint before it holding its state if needed.
+    fn checkpoint_state(&self, index: usize) -> Vec<TokenId> {
+        let history = self.history.as_ref().expect("history is kept");
+        let anchor = history.anchor(index);
+
+        let state = history.checkpoints[anchor].state.clone().expect("anchors hold their state");
+        let steps = history.checkpoints[index].steps - history.checkpoints[anchor].steps;
+
+        if steps == 0 {
+            state
+        } else {
+            self.advance(state, steps)
+        }
+    }
+
+    /// Replaces the current state with `state`, keeping the number of steps.
+    ///
+    /// Like every edit, this is recorded in the history if one is kept, and
+    /// restarts provenance tracking at the current generation. Fails if a
+    /// handle doesn't belong to this system's arena; ids read from a state
+    /// can be branded with [`Arena::handle`].
+    pub fn set_state(&mut self, state: Vec<TokenHandle>) -> Result<(), LSystemError> {
+        let state = self.validate(&state)?;
+        self.edit(|current| *current = state);
+
+        Ok(())
+    }
+
+    /// Inserts `tokens` before the token at `index` of the current state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the length of the state.
+    pub fn insert_at(&mut self, index: usize, tokens: &[TokenHandle]) -> Result<(), LSystemError> {
+        let tokens = self.validate(tokens)?;
+        self.edit(|state| {
+            state.splice(index..index, tokens);
+        });
+
+        Ok(())
+    }
+
+    /// Removes the tokens in `range` from the current state, e.g. to prune a
+    /// branch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds of the state.
+    pub fn remove_range<R: RangeBounds<usize>>(&mut self, range: R) {
+        self.edit(|state| {
+            state.drain(range);
+        });
+    }
+
+    /// Replaces the tokens in `range` of the current state with `tokens`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds of the state.
+    pub fn replace_range<R: RangeBounds<usize>>(&mut self, range: R, tokens: &[TokenHandle]) -> Result<(), LSystemError> {
+        let tokens = self.validate(tokens)?;
+        self.edit(|state| {
+            state.splice(range, tokens);
+        });
+
+        Ok(())
+    }
+
+    /// Checks that every handle belongs to this system's arena, flagged as
+    /// taking parameters exactly when its token does, so that the grammar has
+    /// a rule for every id returned.
+    fn validate(&self, tokens: &[TokenHandle]) -> Result<Vec<TokenId>, LSystemError> {
+        match tokens.iter().find(|handle| !self.arena().owns(handle)) {
+            Some(&invalid) => Err(self.arena().invalid_id(invalid)),
+            None => Ok(tokens.iter().map(TokenHandle::id).collect()),
+        }
+    }
+
+    /// Applies `f` to the state, then refills the module values, whose
+    /// indices may have shifted.
+    fn edit<F: FnOnce(&mut Vec<TokenId>)>(&mut self, f: F) {
+        if self.history.is_some() {
+            let checkpoint = self.checkpoint(false);
+            self.record(checkpoint);
+        }
+
+        f(Arc::make_mut(&mut self.state));
+
+        if self.provenance.is_some() {
+            self.provenance = Some(Provenance::new(self.steps));
+        }
+        self.refresh_queries();
+    }
+
+    /// Expands the current state `generations` steps ahead without taking
+    /// them, sharing the expansion of repeated tokens so that generations far
+    /// too long to hold in memory can still be iterated, indexed and rendered.
+    ///
+    /// Fails if the system has production functions, or if the expanded
+    /// generation has more than `u128::MAX` tokens.
+    pub fn expand(&self, generations: usize) -> Result<Expansion, LSystemError> {
+        Expansion::new(self, generations)
+    }
+
+    /// Iterates over the current generation and every one after it, stepping
+    /// the system as the iterator advances, so that it is left at the last
+    /// generation yielded. The iterator never ends; bound it with an adaptor
+    /// such as `take_while` on the length of each generation.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`LSystem::step`].
+    pub fn generations(&mut self) -> Generations<'_> {
+        Generations {
+            system: self,
+            started: false,
+        }
+    }
+
+    /// Steps at most `max` times, stopping as soon as a generation repeats an
+    /// earlier one, starting from the current one. Only the hashes of earlier
+    /// generations are kept; a matching hash is confirmed by replaying the
+    /// steps up to that generation. Systems with production functions may not
+    /// keep cycling once a state repeats, as their successors can depend on
+    /// randomness.
+    pub fn step_until_stable(&mut self, max: usize) -> Option<Cycle> {
+        // Replays don't need the history or provenance of the system.
+        let start = LSystem {
+            grammar: self.grammar.clone(),
+            values: self.values.clone(),
+            seed: self.seed,
+            rng: self.rng.clone(),
+            provenance: None,
+            history: None,
+            state: self.state.clone(),
+            steps: self.steps,
+        };
+
+        let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+        seen.entry(self.state_hash()).or_default().push(self.steps);
+
+        for _ in 0..max {
             self.step();
+
+            let candidates = seen.entry(self.state_hash()).or_default();
+            let repeated = candidates.iter().copied().find(|&step| {
+                let mut replay = start.clone();
+                replay.step_by(step - start.steps);
+                replay.state == self.state
+            });
+
+            if let Some(first) = repeated {
+                return Some(Cycle {
+                    pre_period: first,
+                    period: self.steps - first,
+                });
+            }
+            candidates.push(self.steps);
         }
+
+        None
+    }
+
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.state.hash(&mut hasher);
+        hasher.finish()
     }
 
     pub fn steps(&self) -> usize {
         self.steps
     }
+
+    /// The seed the random number generator is reset to.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn render(&self) -> String {
-        self.state
-            .iter()
-            .map(|id| self.arena.get_token(id).unwrap().name())
-            .collect::<Vec<_>>()
-            .join("")
+        let mut rendered = Vec::new();
+        self.render_to(&mut rendered, &RenderOptions::new())
+            .expect("writing to a vector can't fail");
+
+        String::from_utf8(rendered).expect("token names are valid UTF-8")
+    }
+
+    /// Streams the token names of the current state to `writer`, formatted
+    /// according to `options`. Writes are buffered, so `writer` needn't be.
+    pub fn render_to<W: Write>(&self, writer: &mut W, options: &RenderOptions) -> Result<(), LSystemError> {
+        render::write_tokens(writer, self.grammar.arena(), self.state.iter().copied(), |index| self.module_values(index), options)
     }
 
     pub fn get_state(&self) -> &[TokenId] {
         &self.state
     }
+
+    pub fn arena(&self) -> &Arena {
+        self.grammar.arena()
+    }
+
+    pub fn axiom(&self) -> &[TokenId] {
+        self.grammar.axiom()
+    }
+
+    /// Returns the parameter values of the query or communication module at
+    /// `index` of the current state.
+    pub fn module_values(&self, index: usize) -> Option<&[f64]> {
+        self.values.get(&index).map(Vec::as_slice)
+    }
+}
+
+/// The distinct tokens of `tokens`, in order of first occurrence.
+fn distinct(tokens: &[TokenId]) -> Vec<TokenId> {
+    let mut seen = [false; 128];
+
+    tokens.iter()
+        .copied()
+        .filter(|id| !std::mem::replace(&mut seen[id.value() as usize], true))
+        .collect()
 }
@@ -50,3 +50,732 @@ fn fractal_binary_tree() -> Result<(), LSystemError> {
 
     Ok(())
 }
+
+#[test]
+fn decomposition_applied_to_fixpoint() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+    let d = builder.token("D")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![a, b])?;
+    builder.decomposition_rule(b, vec![c, c])?;
+    builder.decomposition_rule(c, vec![d])?;
+
+    let mut system = builder.finish()?;
+
+    // The axiom itself is not decomposed.
+    assert_eq!(system.render(), "A");
+
+    system.step();
+    assert_eq!(system.render(), "ADD");
+
+    system.step();
+    assert_eq!(system.render(), "ADDDD");
+
+    Ok(())
+}
+
+#[test]
+fn decomposition_depth_is_bounded() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![b])?;
+    builder.decomposition_rule(b, vec![a, b])?;
+    builder.max_decomposition_depth(3);
+
+    let mut system = builder.finish()?;
+
+    system.step();
+    assert_eq!(system.render(), "AAAB");
+
+    Ok(())
+}
+
+struct Light {
+    calls: usize,
+}
+
+impl Environment for Light {
+    fn respond(&mut self, query: &environment::Query) -> Vec<f64> {
+        self.calls += 1;
+        vec![query.turtle.position.y * 10.0]
+    }
+}
+
+fn assert_position(values: Option<&[f64]>, x: f64, y: f64) {
+    let values = values.expect("missing query values");
+    assert!((values[0] - x).abs() < 1e-9 && (values[1] - y).abs() < 1e-9, "{:?}", values);
+}
+
+#[test]
+fn open_lsystem_query_and_communication() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let f = builder.token("F")?;
+    let a = builder.token("A")?;
+    let query = builder.module("?P", 2)?;
+    let light = builder.module("?E", 1)?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![f, query, light, a])?;
+
+    let mut system = builder.finish()?;
+    let mut environment = Light { calls: 0 };
+
+    system.step_with(&mut environment)?;
+    system.step_with(&mut environment)?;
+    assert_eq!(system.render(), "F?P?EF?P?EA");
+    assert_eq!(environment.calls, 3);
+
+    assert_position(system.module_values(1), 0.0, 1.0);
+    assert_eq!(system.module_values(2), Some(&[10.0][..]));
+    assert_position(system.module_values(4), 0.0, 2.0);
+    assert_eq!(system.module_values(5), Some(&[20.0][..]));
+    assert_eq!(system.module_values(6), None);
+
+    // Without an environment only the query modules are filled in.
+    system.step();
+    assert_eq!(system.module_values(2), None);
+    assert_position(system.module_values(7), 0.0, 3.0);
+
+    // The environment has to respond with as many values as the module's arity.
+    struct Silent;
+    impl Environment for Silent {
+        fn respond(&mut self, _query: &environment::Query) -> Vec<f64> {
+            Vec::new()
+        }
+    }
+
+    let state = system.get_state().to_vec();
+    assert!(matches!(
+        system.step_with(&mut Silent),
+        Err(LSystemError::ArityMismatch { expected: 1, found: 0, .. })
+    ));
+
+    // The failed step left the system untouched.
+    assert_eq!(system.get_state(), &state[..]);
+    assert_position(system.module_values(7), 0.0, 3.0);
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_uses_context() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+
+    builder.axiom(vec![a, b, a])?;
+    // A becomes C when it follows a B, and grows otherwise.
+    builder.production_fn(a, move |context| {
+        if context.left() == Some(b) {
+            vec![c]
+        } else {
+            vec![a; context.generation() + 1]
+        }
+    })?;
+
+    let mut system = builder.finish()?;
+
+    system.step();
+    assert_eq!(system.render(), "ABC");
+
+    system.step();
+    assert_eq!(system.render(), "AABC");
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_on_cloned_builder() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+
+    builder.axiom(vec![a, b, a])?;
+    builder.production_fn(a, move |context| {
+        assert_eq!(context.token(), a);
+        if context.left() == Some(b) { vec![c] } else { vec![a] }
+    })?;
+
+    // The clone's arena has a brand of its own, yet the handles it hands
+    // out equal those captured from the original builder.
+    let mut system = builder.clone().finish()?;
+    system.step();
+    assert_eq!(system.render(), "ABC");
+    assert_eq!(system.arena().handle(b.id()), Some(b));
+
+    // Handles of unrelated builders still differ, although their ids match.
+    let x = LSystemBuilder::new().token("X")?;
+    assert_ne!(x, a);
+    assert!(!std::collections::HashSet::from([a]).contains(&x));
+
+    let mut system = builder.finish()?;
+    system.step();
+    assert_eq!(system.render(), "ABC");
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_is_reproducible_with_seed() -> Result<(), LSystemError> {
+    use rand::Rng;
+
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a])?;
+    builder.seed(7);
+    builder.production_fn(a, move |context| {
+        if context.rng().gen_bool(0.5) {
+            vec![a, b]
+        } else {
+            vec![b, a]
+        }
+    })?;
+
+    let mut system = builder.finish()?;
+
+    system.step_by(10);
+    let first = system.render();
+
+    system.reset();
+    system.step_by(10);
+    assert_eq!(system.render(), first);
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_invalid_id() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_fn(a, |_| vec![token::TokenId::new(42, false).into()])?;
+
+    let mut system = builder.finish()?;
+
+    assert!(matches!(system.try_step(), Err(LSystemError::UnbrandedToken(_))));
+    assert_eq!(system.render(), "A");
+    assert_eq!(system.steps(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_misflagged_id() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+
+    // A handle of the right arena, but flagged as taking parameters.
+    let misflagged = token::TokenHandle::new(token::TokenId::new(a.value(), true), a.arena());
+    builder.axiom(vec![a])?;
+    builder.production_fn(a, move |_| vec![misflagged])?;
+
+    let mut system = builder.finish()?;
+
+    assert!(matches!(system.try_step(), Err(LSystemError::MisflaggedTokenId(id)) if id == misflagged));
+    assert_eq!(system.render(), "A");
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_unbalanced_branches() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let open = builder.token("[")?;
+    let close = builder.token("]")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_fn(a, move |_| vec![open, a])?;
+
+    // Without declared branch tokens anything goes.
+    let mut system = builder.clone().finish()?;
+    system.try_step()?;
+    assert_eq!(system.render(), "[A");
+
+    builder.branch_tokens(open, close)?;
+    let mut system = builder.finish()?;
+
+    assert!(matches!(system.try_step(), Err(LSystemError::UnbalancedBranches(rule)) if rule == "A => [A"));
+    assert_eq!(system.render(), "A");
+    assert_eq!(system.steps(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn production_fn_foreign_id() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+    let a = builder.token("A")?;
+    builder.token("C")?;
+
+    let mut other = LSystemBuilder::new();
+    other.token("X")?;
+    let y = other.token("Y")?;
+
+    // `Y` has the value of `C`, but belongs to another builder.
+    builder.axiom(vec![a])?;
+    builder.production_fn(a, move |_| vec![y])?;
+
+    let mut system = builder.finish()?;
+
+    assert!(matches!(system.try_step(), Err(LSystemError::ForeignTokenId(id)) if id == y));
+    assert_eq!(system.render(), "A");
+
+    Ok(())
+}
+
+#[test]
+fn provenance_tracks_parents_and_rules() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![a, b])?;
+    builder.production_rule(b, vec![a])?;
+    builder.decomposition_rule(b, vec![c, b])?;
+    builder.max_decomposition_depth(1);
+
+    let mut system = builder.finish()?;
+    system.track_provenance(true);
+
+    system.step();
+    assert_eq!(system.render(), "ACB");
+    system.step();
+    assert_eq!(system.render(), "ACBCA");
+
+    let provenance = system.provenance().unwrap();
+    let second = provenance.generation(2).unwrap();
+    assert_eq!(second.parents, vec![0, 0, 0, 1, 2]);
+    assert_eq!(second.rules, vec![Some(0), Some(0), Some(0), None, Some(1)]);
+
+    assert_eq!(provenance.lineage(2, 4), Some(vec![4, 2, 0]));
+    assert_eq!(provenance.lineage(3, 0), None);
+
+    system.reset();
+    assert_eq!(system.provenance().unwrap().end(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn provenance_numbers_rules_as_submitted() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    builder.token("A")?;
+    builder.parse_axiom("A")?;
+    assert!(builder.parse_rule("A => Q").is_err());
+    builder.parse_rule("A => AA")?;
+
+    let mut system = builder.finish()?;
+    system.track_provenance(true);
+    system.step();
+
+    let provenance = system.provenance().unwrap();
+    assert_eq!(provenance.generation(1).unwrap().rules, vec![Some(1), Some(1)]);
+
+    Ok(())
+}
+
+#[test]
+fn classify_systems() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let open = builder.token("[")?;
+    let close = builder.token("]")?;
+    builder.branch_tokens(open, close)?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![a, b])?;
+    builder.production_rule(b, vec![a])?;
+
+    let classification = builder.clone().finish()?.classify();
+    assert_eq!(classification.name(), "PD0L");
+    assert_eq!(classification.deterministic, Some(true));
+    assert!(!classification.bracketed);
+
+    builder.production_rule(b, vec![open, a, close])?;
+    let classification = builder.clone().finish()?.classify();
+    assert_eq!(classification.to_string(), "PD0L (bracketed)");
+
+    let mut opaque = builder.clone();
+    opaque.production_fn(a, |context| vec![context.token()])?;
+    let classification = opaque.finish()?.classify();
+    assert_eq!(classification.deterministic, None);
+    assert_eq!(classification.propagating, None);
+    assert_eq!(classification.context_free, None);
+    assert_eq!(classification.to_string(), "L (bracketed)");
+
+    builder.production_rule(b, vec![])?;
+    assert_eq!(builder.clone().finish()?.classify().name(), "D0L");
+
+    // Fixed rules still tell that the system erases tokens.
+    let light = builder.module("?E", 1)?;
+    builder.production_fn(a, move |_| vec![light])?;
+    let classification = builder.finish()?.classify();
+    assert_eq!(classification.propagating, Some(false));
+    assert_eq!(classification.to_string(), "L (parametric)");
+
+    Ok(())
+}
+
+#[test]
+fn step_until_stable_detects_cycles() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let x = builder.token("X")?;
+    let y = builder.token("Y")?;
+
+    builder.production_rule(a, vec![x, a])?;
+    builder.production_rule(x, vec![y])?;
+    builder.production_rule(y, vec![x])?;
+
+    // X and Y flip every step.
+    builder.axiom(vec![x])?;
+    let mut system = builder.clone().finish()?;
+    assert_eq!(system.step_until_stable(10), Some(system::Cycle { pre_period: 0, period: 2 }));
+    assert_eq!(system.steps(), 2);
+
+    // A grows forever.
+    builder.axiom(vec![a])?;
+    let mut system = builder.clone().finish()?;
+    assert_eq!(system.step_until_stable(10), None);
+    assert_eq!(system.steps(), 10);
+
+    // The empty word is a fixed point.
+    builder.production_rule(a, vec![])?;
+    let mut system = builder.finish()?;
+    assert_eq!(system.step_until_stable(10), Some(system::Cycle { pre_period: 1, period: 1 }));
+
+    Ok(())
+}
+
+#[test]
+fn step_by_matches_single_steps() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let c = builder.token("C")?;
+
+    builder.axiom(vec![a, c])?;
+    builder.production_rule(a, vec![a, b])?;
+    builder.production_rule(b, vec![a])?;
+    builder.decomposition_rule(c, vec![b])?;
+
+    let mut stepped = builder.finish()?;
+    let mut jumped = stepped.clone();
+
+    for n in [0, 1, 3, 6] {
+        for _ in 0..n {
+            stepped.step();
+        }
+        jumped.step_by(n);
+
+        assert_eq!(jumped.render(), stepped.render());
+        assert_eq!(jumped.steps(), stepped.steps());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn step_by_ignores_unreachable_rules() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let z = builder.token("Z")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![b])?;
+    builder.production_rule(b, vec![a])?;
+    // Never reached, but would double 2^40 times.
+    builder.production_rule(z, vec![z, z])?;
+
+    let mut system = builder.finish()?;
+    system.step_by(1 << 40);
+    assert_eq!(system.render(), "A");
+
+    system.step_by(33);
+    assert_eq!(system.render(), "B");
+    assert_eq!(system.steps(), (1 << 40) + 33);
+
+    Ok(())
+}
+
+#[test]
+fn render_to_formats_parameters() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let f = builder.token("F")?;
+    let a = builder.token("A")?;
+    let light = builder.module("?E", 1)?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![f, light, a])?;
+
+    let mut system = builder.finish()?;
+    system.step_with(&mut Light { calls: 0 })?;
+
+    let mut rendered = Vec::new();
+    system.render_to(&mut rendered, &RenderOptions::new().separator(" ").parameters(true))?;
+    assert_eq!(String::from_utf8(rendered).unwrap(), "F ?E(10) A");
+
+    // Write errors are passed on.
+    struct Full;
+    impl std::io::Write for Full {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WriteZero.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    assert!(matches!(system.render_to(&mut Full, &RenderOptions::new()), Err(LSystemError::IOError(_))));
+
+    Ok(())
+}
+
+#[test]
+fn simulations_share_grammar_across_threads() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_fn(a, |context| {
+        use rand::Rng;
+        let a = context.token();
+        if context.rng().gen_bool(0.5) {
+            vec![a, a]
+        } else {
+            vec![a]
+        }
+    })?;
+    builder.production_rule(b, vec![a])?;
+    builder.seed(7);
+
+    let system = builder.finish()?;
+    let grammar = system.grammar().clone();
+
+    let handles = (0..4u64)
+        .map(|seed| {
+            let grammar = grammar.clone();
+            std::thread::spawn(move || {
+                let mut system = LSystem::from_grammar(grammar);
+                system.set_seed(seed);
+                system.step_by(8);
+                system.render()
+            })
+        })
+        .collect::<Vec<_>>();
+    let rendered = handles
+        .into_iter()
+        .map(|handle| handle.join().map_err(|_| LSystemError::ThreadError))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Each simulation is reproducible from its seed alone.
+    for (seed, rendered) in rendered.iter().enumerate() {
+        let mut system = LSystem::from_grammar(grammar.clone());
+        system.set_seed(seed as u64);
+        system.step_by(8);
+        assert_eq!(&system.render(), rendered);
+    }
+
+    assert!(std::sync::Arc::ptr_eq(system.clone().grammar(), &grammar));
+    assert_eq!(LSystem::from_grammar(grammar).seed(), 7);
+
+    Ok(())
+}
+
+#[test]
+fn generations_iterate_until_bound() -> Result<(), LSystemError> {
+    let mut system = fixtures::algae()?;
+
+    let lengths = system.generations()
+        .take_while(|generation| generation.len() <= 10)
+        .map(|generation| (generation.step, generation.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(lengths, [(0, 1), (1, 2), (2, 3), (3, 5), (4, 8)]);
+
+    // The system is left at the first generation that was too long.
+    assert_eq!(system.steps(), 5);
+
+    let generation = system.generations().nth(1).unwrap();
+    assert_eq!(generation.step, 6);
+    assert_eq!(*generation.state, system.get_state());
+    // The generation shares the state of the system rather than copying it.
+    assert_eq!(generation.state.as_ptr(), system.get_state().as_ptr());
+
+    Ok(())
+}
+
+#[test]
+fn history_steps_back_and_looks_up_generations() -> Result<(), LSystemError> {
+    let mut reference = fixtures::algae()?;
+    let states = reference.generations()
+        .take(12)
+        .map(|generation| generation.state)
+        .collect::<Vec<_>>();
+
+    let mut system = reference.clone();
+    system.reset();
+    system.keep_history(3);
+
+    system.step();
+    system.step_by(6);
+    system.step();
+    assert_eq!(system.history_len(), 3);
+
+    for (step, state) in states.iter().enumerate().take(9).skip(5) {
+        assert_eq!(&system.generation(step).unwrap().state, state);
+    }
+    assert_eq!(system.generation(4), None);
+
+    assert!(system.step_back());
+    assert!(system.step_back());
+    assert_eq!(system.steps(), 6);
+    assert_eq!(system.get_state(), &states[6][..]);
+
+    system.step_by(3);
+    assert_eq!(system.get_state(), &states[9][..]);
+
+    system.keep_history(0);
+    assert!(!system.step_back());
+
+    Ok(())
+}
+
+#[test]
+fn history_of_stochastic_system_is_exact() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_fn(a, move |context| {
+        use rand::Rng;
+        if context.rng().gen_bool(0.5) {
+            vec![a, b]
+        } else {
+            vec![b, a]
+        }
+    })?;
+    builder.seed(3);
+
+    let mut system = builder.finish()?;
+    system.keep_history(10);
+
+    system.step_by(6);
+    let sixth = system.render();
+
+    system.step_back();
+    system.step_back();
+    system.step_by(2);
+    assert_eq!(system.render(), sixth);
+
+    Ok(())
+}
+
+#[test]
+fn state_edits_are_validated_and_undoable() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    let b = builder.token("B")?;
+    let open = builder.token("[")?;
+    let close = builder.token("]")?;
+
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![b, open, a, close, a])?;
+
+    let mut system = builder.finish()?;
+    system.keep_history(4);
+
+    system.step();
+    assert_eq!(system.render(), "B[A]A");
+
+    // Prune the branch, then graft another one.
+    system.remove_range(1..4);
+    assert_eq!(system.render(), "BA");
+    system.insert_at(1, &[open, b, close])?;
+    assert_eq!(system.render(), "B[B]A");
+    system.replace_range(..1, &[a, a])?;
+    assert_eq!(system.render(), "AA[B]A");
+
+    system.step();
+    assert_eq!(system.render(), "B[A]AB[A]A[B]B[A]A");
+
+    assert!(system.step_back());
+    assert_eq!(system.render(), "AA[B]A");
+    assert!(system.step_back());
+    assert_eq!(system.render(), "B[B]A");
+    assert_eq!(system.steps(), 1);
+
+    system.set_state(vec![b])?;
+    assert_eq!(system.render(), "B");
+
+    let foreign = LSystemBuilder::new().token("B")?;
+    assert!(matches!(system.set_state(vec![foreign]), Err(LSystemError::ForeignTokenId(_))));
+    let unbranded = system.insert_at(0, &[token::TokenId::from(0).into()]).unwrap_err();
+    assert!(matches!(unbranded, LSystemError::UnbrandedToken(_)));
+    assert_eq!(unbranded.to_string(), "token ID `0` belongs to no arena");
+    assert_eq!(system.render(), "B");
+
+    Ok(())
+}
+
+#[test]
+fn state_edits_reject_misflagged_ids() -> Result<(), LSystemError> {
+    let mut builder = LSystemBuilder::new();
+
+    let a = builder.token("A")?;
+    builder.axiom(vec![a])?;
+    builder.production_rule(a, vec![a, a])?;
+
+    let mut system = builder.finish()?;
+
+    // `A` takes no parameters, so the grammar has no rule for this id.
+    let misflagged = token::TokenId::new(a.value(), true);
+    assert_eq!(system.arena().handle(misflagged), None);
+
+    let handle = token::TokenHandle::new(misflagged, a.arena());
+    assert!(matches!(system.set_state(vec![handle]), Err(LSystemError::MisflaggedTokenId(_))));
+    assert!(matches!(system.insert_at(0, &[handle]), Err(LSystemError::MisflaggedTokenId(_))));
+    assert!(matches!(system.replace_range(.., &[handle]), Err(LSystemError::MisflaggedTokenId(_))));
+
+    system.step();
+    assert_eq!(system.render(), "AA");
+
+    Ok(())
+}
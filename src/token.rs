@@ -1,4 +1,6 @@
 use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use crate::LSystemError;
 
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
@@ -9,34 +11,67 @@ pub struct Token {
 
 impl Token {
 
+    /// Creates a token without parameters.
     pub fn new<T: Into<String>>(name: T) -> Result<Self, LSystemError> {
+        Self::with_arity(name, 0)
+    }
+
+    /// Creates a module taking `arity` parameters.
+    pub fn with_arity<T: Into<String>>(name: T, arity: u8) -> Result<Self, LSystemError> {
         let name = name.into();
 
         if name.contains(' ') {
             Err(LSystemError::InvalidToken(name))
         } else {
-            let param = parse_param(&name);
-            Ok(Self { name, param })
+            Ok(Self { name, param: arity })
         }
     }
 
+    /// Creates a token whose arity is given by the trailing digits of its
+    /// name, so that `F2` takes two parameters.
+    pub fn with_inferred_arity<T: Into<String>>(name: T) -> Result<Self, LSystemError> {
+        let name = name.into();
+        let arity = parse_param(&name);
+
+        Self::with_arity(name, arity)
+    }
+
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
 
+    /// Number of parameters the token takes.
     pub fn param(&self) -> u8 {
         self.param
     }
 }
 
 
-impl Into<Token> for &str {
-    fn into(self) -> Token {
-        Token::new(self).unwrap()
+impl From<&str> for Token {
+    fn from(name: &str) -> Self {
+        Token::new(name).unwrap()
+    }
+}
+
+/// Identifies the [`Arena`](crate::Arena) a [`TokenHandle`] was created by, so
+/// that handles can't be used with another arena by accident.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default)]
+pub struct ArenaId(u64);
+
+impl ArenaId {
+    /// The brand of handles that weren't created by an arena, such as those
+    /// converted from a bare [`TokenId`]. They are valid in no arena.
+    pub const NONE: ArenaId = ArenaId(0);
+
+    pub(crate) fn next() -> Self {
+        // Wide enough to never wrap around in practice.
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
     }
 }
 
-#[derive(Debug, Copy, Eq, Hash, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
 pub struct TokenId(pub u8);
 
 impl TokenId {
@@ -68,31 +103,93 @@ impl Display for TokenId{
     }
 }
 
-impl Clone for TokenId {
-    fn clone(&self) -> Self {
-        Self(self.0)
-    }
-}
-
 impl From<u8> for TokenId {
     fn from(id: u8) -> Self {
         Self(id)
     }
 }
 
+/// A [`TokenId`] branded with the [`Arena`](crate::Arena) that created it, as
+/// returned by [`LSystemBuilder::token`]. Builders and the edits of a system
+/// only accept handles of their own arena, while states hold bare ids.
+///
+/// [`LSystemBuilder::token`]: crate::LSystemBuilder::token
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub struct TokenHandle {
+    id: TokenId,
+    arena: ArenaId,
+}
+
+impl TokenHandle {
+    pub(crate) fn new(id: TokenId, arena: ArenaId) -> Self {
+        Self { id, arena }
+    }
+
+    pub fn id(&self) -> TokenId {
+        self.id
+    }
+
+    pub fn arena(&self) -> ArenaId {
+        self.arena
+    }
+
+    pub fn value(&self) -> u8 {
+        self.id.value()
+    }
+
+    pub fn has_param(&self) -> bool {
+        self.id.has_param()
+    }
+}
+
+impl Display for TokenHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.id.fmt(f)
+    }
+}
+
+/// An unbranded handle, accepted by no arena; see [`Arena::handle`] to brand
+/// an id read from a state.
+///
+/// [`Arena::handle`]: crate::Arena::handle
+impl From<TokenId> for TokenHandle {
+    fn from(id: TokenId) -> Self {
+        Self::new(id, ArenaId::NONE)
+    }
+}
+
+impl From<TokenHandle> for TokenId {
+    fn from(handle: TokenHandle) -> Self {
+        handle.id
+    }
+}
+
+impl PartialEq<TokenId> for TokenHandle {
+    fn eq(&self, other: &TokenId) -> bool {
+        self.id == *other
+    }
+}
+
+impl PartialEq<TokenHandle> for TokenId {
+    fn eq(&self, other: &TokenHandle) -> bool {
+        *self == other.id
+    }
+}
+
 
 /**
  * Returns the parameter number of a token, if it has one.
  */
 fn parse_param(name: &str) -> u8 {
-    let mut chars = name.chars().rev();
+    let chars = name.chars().rev();
     let mut param = 0;
     let mut multiplier = 1;
 
-    while let Some(c) = chars.next() {
-        if c.is_digit(10) {
-            param += c.to_digit(10).unwrap() * multiplier;
-            multiplier *= 10;
+    for c in chars {
+        if c.is_ascii_digit() {
+            // Saturates on long suffixes, which are capped below anyway.
+            param = c.to_digit(10).unwrap().saturating_mul(multiplier).saturating_add(param);
+            multiplier = multiplier.saturating_mul(10);
         } else {
             break;
         }
@@ -109,3 +206,25 @@ impl Display for Token {
         write!(f, "{}", self.name())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arity_is_explicit_by_default() {
+        assert_eq!(Token::new("F2").unwrap().param(), 0);
+        assert_eq!(Token::with_arity("F", 2).unwrap().param(), 2);
+        assert_eq!(Token::with_inferred_arity("F2").unwrap().param(), 2);
+        assert_eq!(Token::with_inferred_arity("Layer10").unwrap().param(), 10);
+        assert_eq!(Token::with_inferred_arity("Layer300").unwrap().param(), 255);
+        assert_eq!(Token::with_inferred_arity("Layer12345678901").unwrap().param(), 255);
+        assert_eq!(Token::with_inferred_arity("Layer00000000001").unwrap().param(), 1);
+    }
+
+    #[test]
+    fn token_id_is_a_byte() {
+        assert_eq!(std::mem::size_of::<TokenId>(), 1);
+        assert_eq!(TokenId::new(3, true), TokenHandle::from(TokenId::new(3, true)));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::Arena;
+use crate::token::TokenId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64) -> Self {
+        Self { x, y }
+    }
+
+    pub fn distance(&self, other: &Point) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+/// A line drawn by the turtle, together with the branch depth it was drawn at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub from: Point,
+    pub to: Point,
+    pub depth: usize,
+    /// Index of the token that drew the segment.
+    pub index: usize,
+}
+
+impl Segment {
+    pub fn length(&self) -> f64 {
+        self.from.distance(&self.to)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TurtleState {
+    pub position: Point,
+    /// Heading in degrees, counter-clockwise from the positive x axis.
+    pub heading: f64,
+}
+
+impl Default for TurtleState {
+    fn default() -> Self {
+        Self {
+            position: Point::default(),
+            heading: 90.0,
+        }
+    }
+}
+
+/// The result of running a turtle over a state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interpretation {
+    /// Turtle state at each token, before the token is interpreted.
+    pub states: Vec<TurtleState>,
+    pub segments: Vec<Segment>,
+}
+
+/// A 2D turtle interpreting tokens by name:
+///
+/// * `F`, `G` move forward drawing a segment
+/// * `f` moves forward without drawing
+/// * `+`, `-` turn left and right by the configured angle
+/// * `|` turns around
+///
+/// The branch tokens passed to [`Turtle::interpret`] push and pop the turtle
+/// state, see [`LSystem::branch_tokens`](crate::LSystem::branch_tokens).
+///
+/// All other tokens leave the turtle untouched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Turtle {
+    step: f64,
+    angle: f64,
+}
+
+impl Turtle {
+    /// Creates a turtle moving `step` units forward and turning `angle` degrees.
+    pub fn new(step: f64, angle: f64) -> Self {
+        Self { step, angle }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    pub fn angle(&self) -> f64 {
+        self.angle
+    }
+
+    /// Walks `state`, pushing the turtle state on `branches.0` and popping it
+    /// on `branches.1`.
+    pub fn interpret(&self, arena: &Arena, state: &[TokenId], branches: Option<(TokenId, TokenId)>) -> Interpretation {
+        let mut current = TurtleState::default();
+        let mut stack = Vec::new();
+        let mut interpretation = Interpretation {
+            states: Vec::with_capacity(state.len()),
+            segments: Vec::new(),
+        };
+
+        for (index, id) in state.iter().enumerate() {
+            interpretation.states.push(current);
+
+            match branches {
+                Some((open, _)) if *id == open => {
+                    stack.push(current);
+                    continue;
+                }
+                Some((_, close)) if *id == close => {
+                    if let Some(previous) = stack.pop() {
+                        current = previous;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let Some(token) = arena.get_token(id) else {
+                continue;
+            };
+
+            match token.name() {
+                "F" | "G" => {
+                    let from = current.position;
+                    current.position = self.forward(&current);
+                    interpretation.segments.push(Segment {
+                        from,
+                        to: current.position,
+                        depth: stack.len(),
+                        index,
+                    });
+                }
+                "f" => current.position = self.forward(&current),
+                "+" => current.heading += self.angle,
+                "-" => current.heading -= self.angle,
+                "|" => current.heading += 180.0,
+                _ => {}
+            }
+        }
+
+        interpretation
+    }
+
+    fn forward(&self, state: &TurtleState) -> Point {
+        let radians = state.heading.to_radians();
+        Point::new(
+            state.position.x + self.step * radians.cos(),
+            state.position.y + self.step * radians.sin(),
+        )
+    }
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Self::new(1.0, 90.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turtle_branches_restore_state() {
+        let mut arena = Arena::new();
+
+        let f = arena.push_token("F".into());
+        let plus = arena.push_token("+".into());
+        let open = arena.push_token("[".into());
+        let close = arena.push_token("]".into());
+
+        let turtle = Turtle::new(1.0, 90.0);
+        let state = [f, open, plus, f, close, f].map(TokenId::from);
+        let interpretation = turtle.interpret(&arena, &state, Some((open.id(), close.id())));
+
+        assert_eq!(interpretation.segments.len(), 3);
+        assert_eq!(interpretation.segments[1].depth, 1);
+
+        let end = interpretation.segments[2].to;
+        assert!((end.x - 0.0).abs() < 1e-9);
+        assert!((end.y - 2.0).abs() < 1e-9);
+
+        let branch_end = interpretation.segments[1].to;
+        assert!((branch_end.x + 1.0).abs() < 1e-9);
+        assert!((branch_end.y - 1.0).abs() < 1e-9);
+
+        // Without branch tokens, brackets are plain tokens.
+        let interpretation = turtle.interpret(&arena, &state, None);
+        assert!(interpretation.segments.iter().all(|segment| segment.depth == 0));
+    }
+}